@@ -0,0 +1,596 @@
+//! Tick and liquidity math of concentrated liquidity pools.
+//!
+//! Prices are square roots of the price of x in y, Q64.64. Tick `i` is the
+//! price `1.0001^i`, liquidity `L` between two ticks holds
+//! `L * (1 / sqrt_a - 1 / sqrt_b)` of x and `L * (sqrt_b - sqrt_a)` of y.
+//! Amounts follow the rounding of `math`, in favour of the pool.
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use alloc::vec::Vec;
+
+use crate::math::{self, Rounding, U256};
+
+/// Lowest tick, its square root price is just above `2^-32`.
+pub const MIN_TICK: i32 = -443636;
+
+/// Highest tick, its square root price is just below `2^32`.
+pub const MAX_TICK: i32 = 443636;
+
+/// Square root price of `MIN_TICK`.
+pub const MIN_SQRT_PRICE: u128 = 4295048017;
+
+/// Square root price of `MAX_TICK`.
+pub const MAX_SQRT_PRICE: u128 = 79226673515401279992447579062;
+
+/// Initialized ticks a pool holds at most. Every swap reads all of them, so
+/// the cap keeps swaps within the compute budget.
+pub const MAX_TICKS: usize = 128;
+
+/// Least liquidity a position holds unless it is empty, so positions taking
+/// up the limited ticks are not free.
+pub const MIN_POSITION_LIQUIDITY: u128 = 1_000_000;
+
+/// `log2(1.0001)`, Q64.64.
+const LOG2_TICK_BASE_Q64: i128 = 2661169563308229;
+
+/// `1.0001^(-2^i / 2)` in Q0.128 for every bit `i` of a tick.
+const TICK_RATIOS: [u128; 19] = [
+    340265354078544963557816517032075149313,
+    340248342086729790484326174814286782777,
+    340214320654664324051920982716015181259,
+    340146287995602323631171512101879684303,
+    340010263488231146823593991679159461443,
+    339738377640345403697157401104375502015,
+    339195258003219555707034227454543997024,
+    338111622100601834656805679988414885970,
+    335954724994790223023589805789778977699,
+    331682121138379247127172139078559817299,
+    323299236684853023288211250268160618738,
+    307163716377032989948697243942600083928,
+    277268403626896220162999269216087595045,
+    225923453940442621947126027127485391332,
+    149997214084966997727330242082538205942,
+    66119101136024775622716233608466517925,
+    12847376061809297530290974190478138312,
+    485053260817066172746253684029974020,
+    691415978906521570653435304214167,
+];
+
+/// Square root price of `tick`, Q64.64. `None` outside of the tick range.
+pub fn sqrt_price_at_tick(tick: i32) -> Option<u128> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return None;
+    }
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = U256::one() << 128;
+    for (bit, tick_ratio) in TICK_RATIOS.iter().enumerate() {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = (ratio * U256::from(*tick_ratio)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+    // Q128 to Q64 rounding up, so the price never lands below its tick
+    let remainder = !(ratio & ((U256::one() << 64) - 1)).is_zero();
+    let sqrt_price = (ratio >> 64) + if remainder { U256::one() } else { U256::zero() };
+    u128::try_from(sqrt_price).ok()
+}
+
+/// Greatest tick whose square root price is not above `sqrt_price`.
+pub fn tick_at_sqrt_price(sqrt_price: u128) -> Option<i32> {
+    if !(MIN_SQRT_PRICE..=MAX_SQRT_PRICE).contains(&sqrt_price) {
+        return None;
+    }
+    // log_1.0001(price) = 2 * log2(sqrt_price) / log2(1.0001), then fixed up
+    // against the exact tick prices
+    let log2 = math::log2_q64(sqrt_price)?;
+    let estimate = (2 * log2).div_euclid(LOG2_TICK_BASE_Q64);
+    let mut tick = i32::try_from(estimate).ok()?.clamp(MIN_TICK, MAX_TICK);
+    while tick > MIN_TICK && sqrt_price_at_tick(tick)? > sqrt_price {
+        tick -= 1;
+    }
+    while tick < MAX_TICK && sqrt_price_at_tick(tick + 1)? <= sqrt_price {
+        tick += 1;
+    }
+    Some(tick)
+}
+
+/// Orders two square root prices.
+fn sorted(sqrt_a: u128, sqrt_b: u128) -> (u128, u128) {
+    if sqrt_a > sqrt_b {
+        (sqrt_b, sqrt_a)
+    } else {
+        (sqrt_a, sqrt_b)
+    }
+}
+
+/// `a * b / c` with a 256-bit intermediate product.
+fn mul_div_256(a: U256, b: U256, c: U256, rounding: Rounding) -> Option<U256> {
+    if c.is_zero() {
+        return None;
+    }
+    let product = a.checked_mul(b)?;
+    let (quotient, remainder) = product.div_mod(c);
+    match rounding {
+        Rounding::Up if !remainder.is_zero() => quotient.checked_add(U256::one()),
+        _ => Some(quotient),
+    }
+}
+
+/// Amount of x held by `liquidity` between two square root prices,
+/// `L * (sqrt_b - sqrt_a) / (sqrt_a * sqrt_b)`.
+pub fn amount_x_delta(
+    sqrt_a: u128,
+    sqrt_b: u128,
+    liquidity: u128,
+    rounding: Rounding,
+) -> Option<u64> {
+    let (sqrt_a, sqrt_b) = sorted(sqrt_a, sqrt_b);
+    if sqrt_a == 0 {
+        return None;
+    }
+    let numerator = U256::from(liquidity) << 64;
+    let amount = mul_div_256(
+        numerator,
+        U256::from(sqrt_b - sqrt_a),
+        U256::from(sqrt_b),
+        rounding,
+    )?;
+    let amount = mul_div_256(amount, U256::one(), U256::from(sqrt_a), rounding)?;
+    u64::try_from(amount).ok()
+}
+
+/// Amount of y held by `liquidity` between two square root prices,
+/// `L * (sqrt_b - sqrt_a)`.
+pub fn amount_y_delta(
+    sqrt_a: u128,
+    sqrt_b: u128,
+    liquidity: u128,
+    rounding: Rounding,
+) -> Option<u64> {
+    let (sqrt_a, sqrt_b) = sorted(sqrt_a, sqrt_b);
+    math::to_u64(math::mul_div(
+        liquidity,
+        sqrt_b - sqrt_a,
+        math::Q64,
+        rounding,
+    )?)
+}
+
+/// Amounts of x and y held by `liquidity` between `sqrt_lower` and
+/// `sqrt_upper` at `sqrt_price`, `[x_amount, y_amount]`.
+pub fn amounts_for_liquidity(
+    sqrt_price: u128,
+    sqrt_lower: u128,
+    sqrt_upper: u128,
+    liquidity: u128,
+    rounding: Rounding,
+) -> Option<[u64; 2]> {
+    if sqrt_price <= sqrt_lower {
+        Some([
+            amount_x_delta(sqrt_lower, sqrt_upper, liquidity, rounding)?,
+            0,
+        ])
+    } else if sqrt_price < sqrt_upper {
+        Some([
+            amount_x_delta(sqrt_price, sqrt_upper, liquidity, rounding)?,
+            amount_y_delta(sqrt_lower, sqrt_price, liquidity, rounding)?,
+        ])
+    } else {
+        Some([
+            0,
+            amount_y_delta(sqrt_lower, sqrt_upper, liquidity, rounding)?,
+        ])
+    }
+}
+
+/// Greatest liquidity between `sqrt_lower` and `sqrt_upper` at `sqrt_price`
+/// that `max_x` and `max_y` can fund, rounded down.
+pub fn liquidity_for_amounts(
+    sqrt_price: u128,
+    sqrt_lower: u128,
+    sqrt_upper: u128,
+    max_x: u64,
+    max_y: u64,
+) -> Option<u128> {
+    // x * sqrt_a * sqrt_b / (sqrt_b - sqrt_a)
+    let liquidity_x = |sqrt_a: u128, sqrt_b: u128| -> Option<u128> {
+        let product = math::mul_div(max_x as u128, sqrt_a, math::Q64, Rounding::Down)?;
+        math::mul_div(product, sqrt_b, sqrt_b - sqrt_a, Rounding::Down)
+    };
+    // y / (sqrt_b - sqrt_a)
+    let liquidity_y = |sqrt_a: u128, sqrt_b: u128| -> Option<u128> {
+        math::mul_div(max_y as u128, math::Q64, sqrt_b - sqrt_a, Rounding::Down)
+    };
+    if sqrt_lower >= sqrt_upper {
+        return None;
+    }
+    if sqrt_price <= sqrt_lower {
+        liquidity_x(sqrt_lower, sqrt_upper)
+    } else if sqrt_price < sqrt_upper {
+        Some(core::cmp::min(
+            liquidity_x(sqrt_price, sqrt_upper)?,
+            liquidity_y(sqrt_lower, sqrt_price)?,
+        ))
+    } else {
+        liquidity_y(sqrt_lower, sqrt_upper)
+    }
+}
+
+/// Square root price after `amount` of x is added to (`add`) or removed
+/// from the liquidity, `L * sqrt_p / (L + amount * sqrt_p)` rounded up.
+fn sqrt_price_from_amount_x(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount: u64,
+    add: bool,
+) -> Option<u128> {
+    if amount == 0 {
+        return Some(sqrt_price);
+    }
+    let numerator = U256::from(liquidity) << 64;
+    let product = U256::from(amount).checked_mul(U256::from(sqrt_price))?;
+    let denominator = if add {
+        numerator.checked_add(product)?
+    } else {
+        numerator.checked_sub(product).filter(|d| !d.is_zero())?
+    };
+    let next = mul_div_256(numerator, U256::from(sqrt_price), denominator, Rounding::Up)?;
+    u128::try_from(next).ok()
+}
+
+/// Square root price after `amount` of y is added to (`add`) or removed
+/// from the liquidity, `sqrt_p +- amount / L` rounded down.
+fn sqrt_price_from_amount_y(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount: u64,
+    add: bool,
+) -> Option<u128> {
+    if add {
+        let delta = math::mul_div(amount as u128, math::Q64, liquidity, Rounding::Down)?;
+        sqrt_price.checked_add(delta)
+    } else {
+        let delta = math::mul_div(amount as u128, math::Q64, liquidity, Rounding::Up)?;
+        sqrt_price.checked_sub(delta).filter(|p| *p > 0)
+    }
+}
+
+/// One step of a swap within a range of constant liquidity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapStep {
+    /// Square root price the step ends at.
+    pub sqrt_price: u128,
+    /// Amount of the from token paid, commision excluded, rounded up.
+    pub amount_in: u64,
+    /// Amount of the to token bought, rounded down.
+    pub amount_out: u64,
+}
+
+/// Swaps at most `amount_remaining` between `sqrt_price` and `sqrt_target`
+/// at constant `liquidity`. `amount_remaining` is paid in the from token if
+/// `exact_in`, bought in the to token otherwise. The from token is x if
+/// `x_to_y`, which moves the price down.
+pub fn swap_step(
+    sqrt_price: u128,
+    sqrt_target: u128,
+    liquidity: u128,
+    amount_remaining: u64,
+    exact_in: bool,
+    x_to_y: bool,
+) -> Option<SwapStep> {
+    let amount_in_to = |sqrt_next: u128, rounding: Rounding| {
+        if x_to_y {
+            amount_x_delta(sqrt_next, sqrt_price, liquidity, rounding)
+        } else {
+            amount_y_delta(sqrt_price, sqrt_next, liquidity, rounding)
+        }
+    };
+    let amount_out_to = |sqrt_next: u128, rounding: Rounding| {
+        if x_to_y {
+            amount_y_delta(sqrt_next, sqrt_price, liquidity, rounding)
+        } else {
+            amount_x_delta(sqrt_price, sqrt_next, liquidity, rounding)
+        }
+    };
+
+    let sqrt_next = if liquidity == 0 {
+        sqrt_target
+    } else if exact_in {
+        if amount_remaining >= amount_in_to(sqrt_target, Rounding::Up)? {
+            sqrt_target
+        } else if x_to_y {
+            sqrt_price_from_amount_x(sqrt_price, liquidity, amount_remaining, true)?
+        } else {
+            sqrt_price_from_amount_y(sqrt_price, liquidity, amount_remaining, true)?
+        }
+    } else if amount_remaining >= amount_out_to(sqrt_target, Rounding::Down)? {
+        sqrt_target
+    } else if x_to_y {
+        sqrt_price_from_amount_y(sqrt_price, liquidity, amount_remaining, false)?
+    } else {
+        sqrt_price_from_amount_x(sqrt_price, liquidity, amount_remaining, false)?
+    };
+
+    let amount_in = amount_in_to(sqrt_next, Rounding::Up)?;
+    let mut amount_out = amount_out_to(sqrt_next, Rounding::Down)?;
+    if !exact_in {
+        amount_out = core::cmp::min(amount_out, amount_remaining);
+    }
+    Some(SwapStep {
+        sqrt_price: sqrt_next,
+        amount_in,
+        amount_out,
+    })
+}
+
+/// Commision growth per unit of liquidity inside `[tick_lower, tick_upper)`,
+/// Q64.64, from the growth outside both ticks. Wraps like the growth does.
+pub fn fee_growth_inside(
+    tick_current: i32,
+    fee_growth: u128,
+    tick_lower: i32,
+    outside_lower: u128,
+    tick_upper: i32,
+    outside_upper: u128,
+) -> u128 {
+    let below = if tick_current >= tick_lower {
+        outside_lower
+    } else {
+        fee_growth.wrapping_sub(outside_lower)
+    };
+    let above = if tick_current < tick_upper {
+        outside_upper
+    } else {
+        fee_growth.wrapping_sub(outside_upper)
+    };
+    fee_growth.wrapping_sub(below).wrapping_sub(above)
+}
+
+/// `liquidity + delta`, `None` if it leaves the `u128` range.
+pub fn add_liquidity_delta(liquidity: u128, delta: i128) -> Option<u128> {
+    if delta < 0 {
+        liquidity.checked_sub(delta.unsigned_abs())
+    } else {
+        liquidity.checked_add(delta.unsigned_abs())
+    }
+}
+
+/// Initialized tick of a concentrated liquidity pool, a bound of at least
+/// one position.
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tick {
+    pub index: i32,
+    /// Liquidity added when the price crosses the tick upwards, removed
+    /// when it crosses downwards.
+    pub liquidity_net: i128,
+    /// Liquidity of all positions bounded by the tick.
+    pub liquidity_gross: u128,
+    /// Commision growth on the other side of the tick from the current
+    /// price, Q64.64. Wraps on overflow.
+    pub fee_growth_outside_x: u128,
+    pub fee_growth_outside_y: u128,
+}
+
+/// Initialized ticks of a concentrated liquidity pool, sorted by index.
+/// Kept in one account so a swap always sees every tick it crosses, at most
+/// `MAX_TICKS` of them.
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ticks {
+    pub ticks: Vec<Tick>,
+}
+
+impl Ticks {
+    pub fn get(&self, index: i32) -> Option<&Tick> {
+        let position = self
+            .ticks
+            .binary_search_by_key(&index, |tick| tick.index)
+            .ok()?;
+        Some(&self.ticks[position])
+    }
+
+    /// Greatest initialized tick at or below `tick`.
+    pub fn next_below(&self, tick: i32) -> Option<i32> {
+        let position = self.ticks.partition_point(|t| t.index <= tick);
+        position
+            .checked_sub(1)
+            .map(|position| self.ticks[position].index)
+    }
+
+    /// Smallest initialized tick above `tick`.
+    pub fn next_above(&self, tick: i32) -> Option<i32> {
+        let position = self.ticks.partition_point(|t| t.index <= tick);
+        self.ticks.get(position).map(|t| t.index)
+    }
+
+    /// Adds `liquidity_delta` of a position to its lower or, if `upper`, its
+    /// upper tick, initializing the tick if needed. A new tick counts all
+    /// the growth so far as below it, as if the price has always been there
+    /// when it is at or above the tick.
+    pub fn update(
+        &mut self,
+        index: i32,
+        liquidity_delta: i128,
+        upper: bool,
+        tick_current: i32,
+        fee_growth_x: u128,
+        fee_growth_y: u128,
+    ) -> Option<()> {
+        let position = match self.ticks.binary_search_by_key(&index, |tick| tick.index) {
+            Ok(position) => position,
+            Err(position) => {
+                let (outside_x, outside_y) = if index <= tick_current {
+                    (fee_growth_x, fee_growth_y)
+                } else {
+                    (0, 0)
+                };
+                self.ticks.insert(
+                    position,
+                    Tick {
+                        index,
+                        liquidity_net: 0,
+                        liquidity_gross: 0,
+                        fee_growth_outside_x: outside_x,
+                        fee_growth_outside_y: outside_y,
+                    },
+                );
+                position
+            }
+        };
+        let tick = &mut self.ticks[position];
+        tick.liquidity_gross = add_liquidity_delta(tick.liquidity_gross, liquidity_delta)?;
+        tick.liquidity_net = if upper {
+            tick.liquidity_net.checked_sub(liquidity_delta)?
+        } else {
+            tick.liquidity_net.checked_add(liquidity_delta)?
+        };
+        Some(())
+    }
+
+    /// Drops the ticks no position is bounded by anymore.
+    pub fn remove_unused(&mut self) {
+        self.ticks.retain(|tick| tick.liquidity_gross > 0);
+    }
+
+    /// Moves the price across tick `index`, flipping its outside growth,
+    /// and returns its net liquidity.
+    pub fn cross(&mut self, index: i32, fee_growth_x: u128, fee_growth_y: u128) -> Option<i128> {
+        let position = self
+            .ticks
+            .binary_search_by_key(&index, |tick| tick.index)
+            .ok()?;
+        let tick = &mut self.ticks[position];
+        tick.fee_growth_outside_x = fee_growth_x.wrapping_sub(tick.fee_growth_outside_x);
+        tick.fee_growth_outside_y = fee_growth_y.wrapping_sub(tick.fee_growth_outside_y);
+        Some(tick.liquidity_net)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_round_trip() {
+        assert_eq!(sqrt_price_at_tick(MIN_TICK), Some(MIN_SQRT_PRICE));
+        assert_eq!(sqrt_price_at_tick(MAX_TICK), Some(MAX_SQRT_PRICE));
+        assert_eq!(sqrt_price_at_tick(0), Some(math::Q64));
+
+        for tick in [MIN_TICK, MIN_TICK + 1, -1, 0, 1, MAX_TICK - 1, MAX_TICK] {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            assert_eq!(tick_at_sqrt_price(sqrt_price), Some(tick));
+            if tick > MIN_TICK {
+                // just below the price of a tick is the tick below
+                assert_eq!(tick_at_sqrt_price(sqrt_price - 1), Some(tick - 1));
+            }
+            if tick < MAX_TICK {
+                // prices rise strictly with the tick
+                assert!(sqrt_price_at_tick(tick + 1).unwrap() > sqrt_price);
+            }
+        }
+
+        assert_eq!(sqrt_price_at_tick(MIN_TICK - 1), None);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK + 1), None);
+        assert_eq!(tick_at_sqrt_price(MIN_SQRT_PRICE - 1), None);
+        assert_eq!(tick_at_sqrt_price(MAX_SQRT_PRICE + 1), None);
+    }
+
+    #[test]
+    fn swap_step_rounding() {
+        let liquidity = 1_000_000_000;
+        let sqrt_price = math::Q64;
+        for x_to_y in [true, false] {
+            let sqrt_target = sqrt_price_at_tick(if x_to_y { -1000 } else { 1000 }).unwrap();
+
+            // within the range, paying in 12345 buys less than 12345
+            let step = swap_step(sqrt_price, sqrt_target, liquidity, 12345, true, x_to_y).unwrap();
+            assert_ne!(step.sqrt_price, sqrt_target);
+            assert!(step.amount_in <= 12345);
+            assert!(step.amount_out < 12345);
+            // swapping the output back never returns more than was paid in
+            let back = swap_step(
+                step.sqrt_price,
+                sqrt_price,
+                liquidity,
+                step.amount_out,
+                true,
+                !x_to_y,
+            )
+            .unwrap();
+            assert!(back.amount_out <= step.amount_in);
+
+            // buying the same output costs no more than was paid in, and
+            // paying that cost buys at least the output
+            let bought = swap_step(
+                sqrt_price,
+                sqrt_target,
+                liquidity,
+                step.amount_out,
+                false,
+                x_to_y,
+            )
+            .unwrap();
+            assert_eq!(bought.amount_out, step.amount_out);
+            assert!(bought.amount_in <= 12345);
+            let paid = swap_step(
+                sqrt_price,
+                sqrt_target,
+                liquidity,
+                bought.amount_in,
+                true,
+                x_to_y,
+            )
+            .unwrap();
+            assert!(paid.amount_out >= bought.amount_out);
+
+            // reaching the target, the input rounds up and the output down
+            let step =
+                swap_step(sqrt_price, sqrt_target, liquidity, u64::MAX, true, x_to_y).unwrap();
+            assert_eq!(step.sqrt_price, sqrt_target);
+            let (amount_in, amount_out) = if x_to_y {
+                (
+                    amount_x_delta(sqrt_target, sqrt_price, liquidity, Rounding::Up),
+                    amount_y_delta(sqrt_target, sqrt_price, liquidity, Rounding::Down),
+                )
+            } else {
+                (
+                    amount_y_delta(sqrt_price, sqrt_target, liquidity, Rounding::Up),
+                    amount_x_delta(sqrt_price, sqrt_target, liquidity, Rounding::Down),
+                )
+            };
+            assert_eq!(Some(step.amount_in), amount_in);
+            assert_eq!(Some(step.amount_out), amount_out);
+            assert!(step.amount_in > step.amount_out);
+        }
+    }
+
+    #[test]
+    fn fee_growth_inside_wrapping() {
+        // inside the range, the growth outside both ticks was recorded
+        // before the global growth wrapped
+        let fee_growth = 5;
+        let outside_lower = u128::MAX - 10;
+        let outside_upper = 3;
+        assert_eq!(
+            fee_growth_inside(0, fee_growth, -10, outside_lower, 10, outside_upper),
+            13
+        );
+
+        // below and above the range only the growth between the ticks counts
+        assert_eq!(fee_growth_inside(-20, 5, -10, 2, 10, u128::MAX), 3);
+        assert_eq!(fee_growth_inside(20, 5, -10, u128::MAX, 10, 2), 3);
+
+        // growth accrued in range is the difference of two inside growths,
+        // even when the global growth wraps in between
+        for (tick_current, accrued) in [(0, 100), (-20, 0), (20, 0)] {
+            let start = fee_growth_inside(tick_current, u128::MAX - 40, -10, 7, 10, 9);
+            let end = fee_growth_inside(tick_current, 59, -10, 7, 10, 9);
+            assert_eq!(end.wrapping_sub(start), accrued);
+        }
+    }
+}
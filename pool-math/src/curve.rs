@@ -0,0 +1,289 @@
+//! Swap invariants a pool can be initialized with.
+//!
+//! Amounts follow the rounding of `math`: the pool never pays out more or
+//! receives less than the exact invariant allows.
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::concentrated::{MAX_SQRT_PRICE, MAX_TICK, MIN_SQRT_PRICE};
+use crate::math::{self, Rounding, U256};
+
+/// Largest StableSwap amplification coefficient.
+pub const MAX_AMP: u64 = 1_000_000;
+
+/// Weighted pool weights are percentages adding up to this.
+pub const WEIGHT_TOTAL: u64 = 100;
+
+/// Newton's method iterations before the StableSwap math gives up.
+const MAX_ITERATIONS: usize = 255;
+
+/// Invariant the pool prices swaps with.
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CurveType {
+    /// `x * y = k`.
+    ConstantProduct,
+    /// Curve StableSwap invariant `4A(x + y) + D = 4AD + D^3 / 4xy` for
+    /// pegged pairs, close to constant sum around the balanced point. A
+    /// higher amplification coefficient `amp` keeps it flat for longer.
+    StableSwap { amp: u64 },
+    /// Balancer weighted product `x^wx * y^wy = k`, each token holds its
+    /// weight in percent of the pool value, 80/20 is `weight_x: 80, weight_y: 20`.
+    Weighted { weight_x: u64, weight_y: u64 },
+    /// Concentrated liquidity: positions provide `x * y = k` liquidity
+    /// between two ticks, multiples of `tick_spacing`, and swaps cross the
+    /// ticks. The pool starts at `initial_sqrt_price`, the square root of
+    /// the price of x in y, Q64.64. Reserves do not price swaps, the pool
+    /// keeps its own price and liquidity.
+    Concentrated {
+        tick_spacing: u16,
+        initial_sqrt_price: u128,
+    },
+}
+
+impl CurveType {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            CurveType::ConstantProduct => true,
+            CurveType::StableSwap { amp } => amp > 0 && amp <= MAX_AMP,
+            CurveType::Weighted { weight_x, weight_y } => {
+                weight_x > 0 && weight_y > 0 && weight_x.checked_add(weight_y) == Some(WEIGHT_TOTAL)
+            }
+            CurveType::Concentrated {
+                tick_spacing,
+                initial_sqrt_price,
+            } => {
+                tick_spacing > 0
+                    && (tick_spacing as i32) <= MAX_TICK
+                    && (MIN_SQRT_PRICE..MAX_SQRT_PRICE).contains(&initial_sqrt_price)
+            }
+        }
+    }
+
+    pub fn is_concentrated(&self) -> bool {
+        matches!(self, CurveType::Concentrated { .. })
+    }
+
+    /// Weights of the from and the to token, swapping x to y if `x_to_y`.
+    fn weights(weight_x: u64, weight_y: u64, x_to_y: bool) -> (u64, u64) {
+        if x_to_y {
+            (weight_x, weight_y)
+        } else {
+            (weight_y, weight_x)
+        }
+    }
+
+    /// Amount of the from token to pay for `amount_out` of the to token,
+    /// commision excluded, rounded up. The from token is x if `x_to_y`.
+    pub fn amount_in(
+        &self,
+        amount_out: u64,
+        reserve_from: u64,
+        reserve_to: u64,
+        x_to_y: bool,
+    ) -> Option<u64> {
+        let new_reserve_to = reserve_to.checked_sub(amount_out)?;
+        match *self {
+            CurveType::ConstantProduct => {
+                math::mul_div_u64(amount_out, reserve_from, new_reserve_to, Rounding::Up)
+            }
+            CurveType::StableSwap { amp } => {
+                let d = stable_invariant(amp, reserve_from, reserve_to)?;
+                let new_reserve_from = stable_reserve(amp, new_reserve_to, d)?;
+                let amount_in = new_reserve_from
+                    .checked_sub(U256::from(reserve_from))?
+                    .checked_add(U256::one())?;
+                u64::try_from(amount_in).ok()
+            }
+            CurveType::Weighted { weight_x, weight_y } => {
+                // from * ((to / (to - out))^(w_to / w_from) - 1)
+                let (weight_from, weight_to) = Self::weights(weight_x, weight_y, x_to_y);
+                let base = math::mul_div(
+                    reserve_to as u128,
+                    math::Q64,
+                    new_reserve_to as u128,
+                    Rounding::Up,
+                )?;
+                let power = math::pow_q64(base, weight_to, weight_from, Rounding::Up)?;
+                math::to_u64(math::mul_div(
+                    reserve_from as u128,
+                    power.saturating_sub(math::Q64),
+                    math::Q64,
+                    Rounding::Up,
+                )?)
+            }
+            CurveType::Concentrated { .. } => None,
+        }
+    }
+
+    /// Amount of the to token received for `amount_in` of the from token,
+    /// commision excluded, rounded down. The from token is x if `x_to_y`.
+    pub fn amount_out(
+        &self,
+        amount_in: u64,
+        reserve_from: u64,
+        reserve_to: u64,
+        x_to_y: bool,
+    ) -> Option<u64> {
+        let new_reserve_from = reserve_from.checked_add(amount_in)?;
+        match *self {
+            CurveType::ConstantProduct => {
+                math::mul_div_u64(amount_in, reserve_to, new_reserve_from, Rounding::Down)
+            }
+            CurveType::StableSwap { amp } => {
+                let d = stable_invariant(amp, reserve_from, reserve_to)?;
+                let new_reserve_to = stable_reserve(amp, new_reserve_from, d)?;
+                let amount_out = U256::from(reserve_to)
+                    .saturating_sub(new_reserve_to)
+                    .saturating_sub(U256::one());
+                u64::try_from(amount_out).ok()
+            }
+            CurveType::Weighted { weight_x, weight_y } => {
+                // to * (1 - (from / (from + in))^(w_from / w_to))
+                let (weight_from, weight_to) = Self::weights(weight_x, weight_y, x_to_y);
+                let base = math::mul_div(
+                    reserve_from as u128,
+                    math::Q64,
+                    new_reserve_from as u128,
+                    Rounding::Up,
+                )?;
+                let power = math::pow_q64(base, weight_from, weight_to, Rounding::Up)?;
+                math::to_u64(math::mul_div(
+                    reserve_to as u128,
+                    math::Q64.saturating_sub(power),
+                    math::Q64,
+                    Rounding::Down,
+                )?)
+            }
+            CurveType::Concentrated { .. } => None,
+        }
+    }
+
+    /// Lp tokens minted for the first deposit of `x` and `y`, before the
+    /// minimum liquidity is locked.
+    pub fn initial_liquidity(&self, x: u64, y: u64) -> Option<u64> {
+        match *self {
+            CurveType::ConstantProduct => math::to_u64(math::sqrt(x as u128 * y as u128)),
+            CurveType::StableSwap { amp } => u64::try_from(stable_invariant(amp, x, y)?).ok(),
+            CurveType::Weighted { weight_x, weight_y } => {
+                // x^(wx / total) * y^(wy / total)
+                let x = math::pow_q64((x as u128) << 64, weight_x, WEIGHT_TOTAL, Rounding::Down)?;
+                let y = math::pow_q64((y as u128) << 64, weight_y, WEIGHT_TOTAL, Rounding::Down)?;
+                math::to_u64(math::mul_div(x, y, math::Q64, Rounding::Down)? >> 64)
+            }
+            CurveType::Concentrated { .. } => None,
+        }
+    }
+
+    /// Marginal price of the base token in the quote token, Q64.64. The base
+    /// token is x if `base_is_x`.
+    pub fn spot_price(
+        &self,
+        reserve_base: u64,
+        reserve_quote: u64,
+        base_is_x: bool,
+    ) -> Option<u128> {
+        if reserve_base == 0 || reserve_quote == 0 {
+            return None;
+        }
+        match *self {
+            CurveType::ConstantProduct => Some(math::price_q64(reserve_base, reserve_quote)),
+            CurveType::StableSwap { amp } => {
+                // ratio of the invariant partial derivatives, multiplied by xy:
+                // (4A * xy + t * y) / (4A * xy + t * x) with t = D^3 / 4xy
+                let d = stable_invariant(amp, reserve_base, reserve_quote)?;
+                let t = stable_d_product(d, reserve_base, reserve_quote)?;
+                let base = U256::from(reserve_base);
+                let quote = U256::from(reserve_quote);
+                let axy = U256::from(amp)
+                    .checked_mul(U256::from(4))?
+                    .checked_mul(base)?
+                    .checked_mul(quote)?;
+                let numerator = axy.checked_add(t.checked_mul(quote)?)?;
+                let denominator = axy.checked_add(t.checked_mul(base)?)?;
+                let price = numerator.checked_mul(U256::from(math::Q64))? / denominator;
+                u128::try_from(price).ok()
+            }
+            CurveType::Weighted { weight_x, weight_y } => {
+                // (quote / w_quote) / (base / w_base)
+                let (weight_base, weight_quote) = Self::weights(weight_x, weight_y, base_is_x);
+                math::mul_div(
+                    reserve_quote as u128 * weight_base as u128,
+                    math::Q64,
+                    reserve_base as u128 * weight_quote as u128,
+                    Rounding::Down,
+                )
+            }
+            CurveType::Concentrated { .. } => None,
+        }
+    }
+}
+
+/// `D^3 / 4xy`, computed the way Curve does to keep the intermediates small.
+fn stable_d_product(d: U256, x: u64, y: u64) -> Option<U256> {
+    let d_p = d.checked_mul(d)? / U256::from(x).checked_mul(U256::from(2))?;
+    Some(d_p.checked_mul(d)? / U256::from(y).checked_mul(U256::from(2))?)
+}
+
+/// StableSwap invariant `D` of the reserves, by Newton's method.
+fn stable_invariant(amp: u64, x: u64, y: u64) -> Option<U256> {
+    if x == 0 || y == 0 {
+        return None;
+    }
+    let ann = U256::from(amp).checked_mul(U256::from(4))?;
+    let sum = U256::from(x) + U256::from(y);
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        let d_p = stable_d_product(d, x, y)?;
+        let previous = d;
+        let numerator = ann
+            .checked_mul(sum)?
+            .checked_add(d_p.checked_mul(U256::from(2))?)?
+            .checked_mul(d)?;
+        let denominator = ann
+            .checked_sub(U256::one())?
+            .checked_mul(d)?
+            .checked_add(d_p.checked_mul(U256::from(3))?)?;
+        d = numerator.checked_div(denominator)?;
+        if abs_diff(d, previous) <= U256::one() {
+            return Some(d);
+        }
+    }
+    None
+}
+
+/// Reserve of one token keeping the invariant `d` while the other one
+/// holds `x`, by Newton's method.
+fn stable_reserve(amp: u64, x: u64, d: U256) -> Option<U256> {
+    if x == 0 {
+        return None;
+    }
+    let ann = U256::from(amp).checked_mul(U256::from(4))?;
+    let x = U256::from(x);
+    let c = d.checked_mul(d)? / x.checked_mul(U256::from(2))?;
+    let c = c.checked_mul(d)? / ann.checked_mul(U256::from(2))?;
+    let b = x.checked_add(d / ann)?;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let previous = y;
+        let numerator = y.checked_mul(y)?.checked_add(c)?;
+        let denominator = y
+            .checked_mul(U256::from(2))?
+            .checked_add(b)?
+            .checked_sub(d)?;
+        y = numerator.checked_div(denominator)?;
+        if abs_diff(y, previous) <= U256::one() {
+            return Some(y);
+        }
+    }
+    None
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
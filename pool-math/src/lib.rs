@@ -0,0 +1,802 @@
+//! Pricing, commision and lp math of the pool on plain numbers.
+//!
+//! The program prices every swap, deposit and withdraw through this crate
+//! and applies the results as they are, so off-chain simulators get the
+//! same results without RPC simulation: fill a `PoolSnapshot` from the
+//! pool, its token accounts and ticks, and call its methods. The crate is
+//! `no_std` and only needs `alloc`, none of the Solana or thiserror
+//! dependencies of the program. The `borsh` feature derives the account
+//! encoding of the curve and the ticks.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod concentrated;
+pub mod curve;
+pub mod math;
+
+use crate::concentrated::Ticks;
+use crate::curve::CurveType;
+use crate::math::{Rounding, U256};
+
+/// Lp tokens minted to the locked account on the first deposit, so the lp
+/// supply can never be drained back to zero.
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+
+/// Failures of the pool math, converted to the matching `PoolError` by the
+/// program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathError {
+    MathOverflow,
+    /// Buying the whole reserve or past the last tick.
+    OverBuy,
+    ZeroProvide,
+    ZeroLiquidity,
+    /// A crossed tick is not in the ticks.
+    InvalidTicks,
+}
+
+/// Where the commision paid to a pool goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommisionSplit {
+    /// Kept in the pool`s token account: the liquidity provider share on a
+    /// pool compounding its commision.
+    pub compounded: u64,
+    /// Moved to the commision account, the protocol share included.
+    pub commision_account: u64,
+    /// Protocol share, collected by the admin.
+    pub protocol_fee: u64,
+    /// Growth of the liquidity provider share held in the commision account
+    /// per lp token, or per unit of liquidity in range, Q64.64.
+    pub fee_growth: u128,
+}
+
+/// Amounts of a swap priced by a `PoolSnapshot`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapResult {
+    /// Amount of the from token paid to the pool curve.
+    pub swap_price: u64,
+    /// Commision of the from token, paid on top of `swap_price`.
+    pub commision_amount: u64,
+    /// Amount of the to token bought.
+    pub amount_out: u64,
+    /// Where `commision_amount` goes. Concentrated liquidity pools accrue
+    /// the growth on every step of the swap, their split has the sum.
+    pub split: CommisionSplit,
+}
+
+impl SwapResult {
+    /// Amount of the from token paid, commision included.
+    pub fn amount_in(&self) -> Result<u64, MathError> {
+        self.swap_price
+            .checked_add(self.commision_amount)
+            .ok_or(MathError::MathOverflow)
+    }
+
+    /// Amount of the from token paid into the pool`s token account.
+    pub fn pool_amount(&self) -> Result<u64, MathError> {
+        self.swap_price
+            .checked_add(self.split.compounded)
+            .ok_or(MathError::MathOverflow)
+    }
+}
+
+/// State of a pool needed to price it. Swaps, deposits and withdraws move
+/// the snapshot as the instructions move the pool and its token accounts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolSnapshot {
+    pub curve: CurveType,
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+    pub protocol_fee_numerator: u64,
+    pub protocol_fee_denominator: u64,
+    pub compound_fees: bool,
+    /// Balance of the pool`s token x account.
+    pub reserve_x: u64,
+    /// Balance of the pool`s token y account.
+    pub reserve_y: u64,
+    /// Supply of the lp mint.
+    pub total_lp: u64,
+    /// Lp tokens staked in fee positions, see `Pool::staked_lp`.
+    pub staked_lp: u64,
+    pub sqrt_price: u128,
+    pub tick_current: i32,
+    pub liquidity: u128,
+    pub fee_growth_x: u128,
+    pub fee_growth_y: u128,
+    /// Initialized ticks, only walked by concentrated liquidity pools.
+    pub ticks: Ticks,
+}
+
+impl PoolSnapshot {
+    pub fn commision(&self, swap_price: u64) -> Result<u64, MathError> {
+        commision_define(swap_price, self.fee_numerator, self.fee_denominator)
+    }
+
+    pub fn protocol_fee(&self, commision_amount: u64) -> Result<u64, MathError> {
+        protocol_fee_define(
+            commision_amount,
+            self.protocol_fee_numerator,
+            self.protocol_fee_denominator,
+        )
+    }
+
+    pub fn split_amount_in(&self, amount_in: u64) -> Result<[u64; 2], MathError> {
+        split_amount_in(amount_in, self.fee_numerator, self.fee_denominator)
+    }
+
+    fn reserves(&self, x_to_y: bool) -> (u64, u64) {
+        if x_to_y {
+            (self.reserve_x, self.reserve_y)
+        } else {
+            (self.reserve_y, self.reserve_x)
+        }
+    }
+
+    /// Splits `commision_amount` paid to the pool. The growth is per staked
+    /// lp token, or per unit of liquidity in range on a concentrated
+    /// liquidity pool. With no lp staked the protocol collects the liquidity
+    /// provider share, no one else could claim it.
+    pub fn commision_split(&self, commision_amount: u64) -> Result<CommisionSplit, MathError> {
+        let protocol_fee = self.protocol_fee(commision_amount)?;
+        let lp_commision_amount = commision_amount
+            .checked_sub(protocol_fee)
+            .ok_or(MathError::MathOverflow)?;
+        if self.compound_fees {
+            return Ok(CommisionSplit {
+                compounded: lp_commision_amount,
+                commision_account: protocol_fee,
+                protocol_fee,
+                fee_growth: 0,
+            });
+        }
+        let total_liquidity = if self.curve.is_concentrated() {
+            self.liquidity
+        } else if self.staked_lp == 0 {
+            return Ok(CommisionSplit {
+                compounded: 0,
+                commision_account: commision_amount,
+                protocol_fee: commision_amount,
+                fee_growth: 0,
+            });
+        } else {
+            self.staked_lp as u128
+        };
+        Ok(CommisionSplit {
+            compounded: 0,
+            commision_account: commision_amount,
+            protocol_fee,
+            fee_growth: fee_growth_define(lp_commision_amount, total_liquidity)?,
+        })
+    }
+
+    /// Swap paying exactly `amount_in`, commision included.
+    pub fn swap_exact_in(&mut self, amount_in: u64, x_to_y: bool) -> Result<SwapResult, MathError> {
+        let (amounts, accrued_growth) = if self.curve.is_concentrated() {
+            let (amounts, growth) = self.concentrated_swap(amount_in, true, x_to_y)?;
+            (amounts, Some(growth))
+        } else {
+            let (reserve_from, reserve_to) = self.reserves(x_to_y);
+            let [swap_price, commision_amount] = self.split_amount_in(amount_in)?;
+            let amount_out = self
+                .curve
+                .amount_out(swap_price, reserve_from, reserve_to, x_to_y)
+                .ok_or(MathError::MathOverflow)?;
+            ([swap_price, commision_amount, amount_out], None)
+        };
+        self.settle_swap(amounts, accrued_growth, x_to_y)
+    }
+
+    /// Swap buying exactly `amount_out`.
+    pub fn swap_exact_out(
+        &mut self,
+        amount_out: u64,
+        x_to_y: bool,
+    ) -> Result<SwapResult, MathError> {
+        let (reserve_from, reserve_to) = self.reserves(x_to_y);
+        if amount_out >= reserve_to {
+            return Err(MathError::OverBuy);
+        }
+
+        let (amounts, accrued_growth) = if self.curve.is_concentrated() {
+            let (amounts, growth) = self.concentrated_swap(amount_out, false, x_to_y)?;
+            (amounts, Some(growth))
+        } else {
+            let swap_price = self
+                .curve
+                .amount_in(amount_out, reserve_from, reserve_to, x_to_y)
+                .ok_or(MathError::MathOverflow)?;
+            ([swap_price, self.commision(swap_price)?, amount_out], None)
+        };
+        self.settle_swap(amounts, accrued_growth, x_to_y)
+    }
+
+    /// Moves the reserves and the lp fee growth by a swap, see
+    /// `commision_split`. Concentrated liquidity swaps have already
+    /// `accrued_growth` on their steps.
+    fn settle_swap(
+        &mut self,
+        [swap_price, commision_amount, amount_out]: [u64; 3],
+        accrued_growth: Option<u128>,
+        x_to_y: bool,
+    ) -> Result<SwapResult, MathError> {
+        let mut split = self.commision_split(commision_amount)?;
+        let new_growth = match accrued_growth {
+            Some(growth) => {
+                split.fee_growth = growth;
+                0
+            }
+            None => split.fee_growth,
+        };
+        let result = SwapResult {
+            swap_price,
+            commision_amount,
+            amount_out,
+            split,
+        };
+        let pool_amount = result.pool_amount()?;
+
+        let (reserve_from, reserve_to, fee_growth_total) = if x_to_y {
+            (
+                &mut self.reserve_x,
+                &mut self.reserve_y,
+                &mut self.fee_growth_x,
+            )
+        } else {
+            (
+                &mut self.reserve_y,
+                &mut self.reserve_x,
+                &mut self.fee_growth_y,
+            )
+        };
+        *reserve_from = reserve_from
+            .checked_add(pool_amount)
+            .ok_or(MathError::MathOverflow)?;
+        *reserve_to = reserve_to
+            .checked_sub(amount_out)
+            .ok_or(MathError::MathOverflow)?;
+        *fee_growth_total = fee_growth_total
+            .checked_add(new_growth)
+            .ok_or(MathError::MathOverflow)?;
+        Ok(result)
+    }
+
+    /// Walks the swap of `amount` through the initialized ticks, moving the
+    /// price and liquidity and accruing the growth of every step,
+    /// `([swap_price, commision_amount, amount_out], accrued_growth)`.
+    fn concentrated_swap(
+        &mut self,
+        amount: u64,
+        exact_in: bool,
+        x_to_y: bool,
+    ) -> Result<([u64; 3], u128), MathError> {
+        let overflow = || MathError::MathOverflow;
+        let mut remaining = amount;
+        let [mut swap_price, mut commision_amount, mut amount_out] = [0u64; 3];
+        let mut accrued_growth = 0u128;
+        while remaining > 0 {
+            let next_tick = if x_to_y {
+                self.ticks.next_below(self.tick_current)
+            } else {
+                self.ticks.next_above(self.tick_current)
+            };
+            let sqrt_target = match next_tick {
+                Some(tick) => concentrated::sqrt_price_at_tick(tick).ok_or_else(overflow)?,
+                None if x_to_y => concentrated::MIN_SQRT_PRICE,
+                None => concentrated::MAX_SQRT_PRICE,
+            };
+            if next_tick.is_none() && self.sqrt_price == sqrt_target {
+                return Err(MathError::OverBuy);
+            }
+
+            let step_amount = if exact_in {
+                self.split_amount_in(remaining)?[0]
+            } else {
+                remaining
+            };
+            let step = concentrated::swap_step(
+                self.sqrt_price,
+                sqrt_target,
+                self.liquidity,
+                step_amount,
+                exact_in,
+                x_to_y,
+            )
+            .ok_or_else(overflow)?;
+            let step_commision = if exact_in && step.sqrt_price != sqrt_target {
+                // the input ends inside the range, its rounding dust is commision
+                remaining.checked_sub(step.amount_in).ok_or_else(overflow)?
+            } else {
+                self.commision(step.amount_in)?
+            };
+            let step_spent = if exact_in {
+                step.amount_in
+                    .checked_add(step_commision)
+                    .ok_or_else(overflow)?
+            } else {
+                step.amount_out
+            };
+            remaining = remaining.checked_sub(step_spent).ok_or_else(overflow)?;
+            swap_price = swap_price
+                .checked_add(step.amount_in)
+                .ok_or_else(overflow)?;
+            commision_amount = commision_amount
+                .checked_add(step_commision)
+                .ok_or_else(overflow)?;
+            amount_out = amount_out
+                .checked_add(step.amount_out)
+                .ok_or_else(overflow)?;
+
+            // the liquidity provider share is rounded down on every step, so
+            // the steps never add up to more than the share of the total
+            let lp_commision = math::mul_div_u64(
+                step_commision,
+                self.protocol_fee_denominator
+                    .checked_sub(self.protocol_fee_numerator)
+                    .ok_or_else(overflow)?,
+                self.protocol_fee_denominator,
+                Rounding::Down,
+            )
+            .ok_or_else(overflow)?;
+            let fee_growth = fee_growth_define(lp_commision, self.liquidity)?;
+            accrued_growth = accrued_growth
+                .checked_add(fee_growth)
+                .ok_or_else(overflow)?;
+            let fee_growth_total = if x_to_y {
+                &mut self.fee_growth_x
+            } else {
+                &mut self.fee_growth_y
+            };
+            *fee_growth_total = fee_growth_total
+                .checked_add(fee_growth)
+                .ok_or_else(overflow)?;
+
+            self.sqrt_price = step.sqrt_price;
+            match next_tick {
+                Some(tick) if step.sqrt_price == sqrt_target => {
+                    let liquidity_net = self
+                        .ticks
+                        .cross(tick, self.fee_growth_x, self.fee_growth_y)
+                        .ok_or(MathError::InvalidTicks)?;
+                    let liquidity_delta = if x_to_y {
+                        liquidity_net.checked_neg().ok_or_else(overflow)?
+                    } else {
+                        liquidity_net
+                    };
+                    self.liquidity =
+                        concentrated::add_liquidity_delta(self.liquidity, liquidity_delta)
+                            .ok_or_else(overflow)?;
+                    self.tick_current = if x_to_y { tick - 1 } else { tick };
+                }
+                _ => {
+                    self.tick_current =
+                        concentrated::tick_at_sqrt_price(step.sqrt_price).ok_or_else(overflow)?;
+                }
+            }
+        }
+
+        Ok(([swap_price, commision_amount, amount_out], accrued_growth))
+    }
+
+    /// Part of `amount` of the from token to swap when providing it single
+    /// sided, see `single_sided_swap_define`.
+    pub fn single_sided_swap_amount(&self, amount: u64, x_to_y: bool) -> Result<u64, MathError> {
+        let (reserve_from, _) = self.reserves(x_to_y);
+        single_sided_swap_define(
+            amount,
+            reserve_from,
+            self.fee_numerator,
+            self.fee_denominator,
+        )
+    }
+
+    /// Deposit taking at most `max_x` and `max_y`,
+    /// `[x_amount, y_amount, new_lp]`, see `deposit_define`.
+    pub fn deposit(&mut self, max_x: u64, max_y: u64) -> Result<[u64; 3], MathError> {
+        if max_x == 0 || max_y == 0 {
+            return Err(MathError::ZeroProvide);
+        }
+
+        let result = deposit_define(
+            max_x,
+            max_y,
+            self.total_lp,
+            self.reserve_x,
+            self.reserve_y,
+            &self.curve,
+        )?;
+        let [x_amount, y_amount, new_lp] = result;
+        let minted = if self.total_lp == 0 {
+            new_lp + MINIMUM_LIQUIDITY
+        } else {
+            new_lp
+        };
+        self.reserve_x = self
+            .reserve_x
+            .checked_add(x_amount)
+            .ok_or(MathError::MathOverflow)?;
+        self.reserve_y = self
+            .reserve_y
+            .checked_add(y_amount)
+            .ok_or(MathError::MathOverflow)?;
+        self.total_lp = self
+            .total_lp
+            .checked_add(minted)
+            .ok_or(MathError::MathOverflow)?;
+        if !self.compound_fees {
+            self.staked_lp = self
+                .staked_lp
+                .checked_add(new_lp)
+                .ok_or(MathError::MathOverflow)?;
+        }
+        Ok(result)
+    }
+
+    /// Withdraw burning `lp_amount`, staked unless the pool compounds its
+    /// commision, `[x_amount, y_amount]`.
+    pub fn withdraw(&mut self, lp_amount: u64) -> Result<[u64; 2], MathError> {
+        let result = liquidity_profit(lp_amount, self.total_lp, self.reserve_x, self.reserve_y)?;
+        let [x_amount, y_amount] = result;
+        self.reserve_x = self
+            .reserve_x
+            .checked_sub(x_amount)
+            .ok_or(MathError::MathOverflow)?;
+        self.reserve_y = self
+            .reserve_y
+            .checked_sub(y_amount)
+            .ok_or(MathError::MathOverflow)?;
+        self.total_lp = self
+            .total_lp
+            .checked_sub(lp_amount)
+            .ok_or(MathError::MathOverflow)?;
+        if !self.compound_fees {
+            self.staked_lp = self
+                .staked_lp
+                .checked_sub(lp_amount)
+                .ok_or(MathError::MathOverflow)?;
+        }
+        Ok(result)
+    }
+}
+
+/// Commision charged on top of `swap_price` at the pool fee rate, rounded up.
+pub fn commision_define(
+    swap_price: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Result<u64, MathError> {
+    math::mul_div_u64(swap_price, fee_numerator, fee_denominator, Rounding::Up)
+        .ok_or(MathError::MathOverflow)
+}
+
+/// Protocol share of `commision_amount`, rounded down in favour of the
+/// liquidity providers.
+pub fn protocol_fee_define(
+    commision_amount: u64,
+    protocol_fee_numerator: u64,
+    protocol_fee_denominator: u64,
+) -> Result<u64, MathError> {
+    math::mul_div_u64(
+        commision_amount,
+        protocol_fee_numerator,
+        protocol_fee_denominator,
+        Rounding::Down,
+    )
+    .ok_or(MathError::MathOverflow)
+}
+
+/// Splits `amount_in` into the swap price and the commision charged on top
+/// of it, `[swap_price, commision_amount]`.
+pub fn split_amount_in(
+    amount_in: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Result<[u64; 2], MathError> {
+    let swap_price = math::to_u64(
+        math::mul_div(
+            amount_in as u128,
+            fee_denominator as u128,
+            fee_denominator as u128 + fee_numerator as u128,
+            Rounding::Down,
+        )
+        .ok_or(MathError::MathOverflow)?,
+    )
+    .ok_or(MathError::MathOverflow)?;
+    let commision_amount = amount_in
+        .checked_sub(swap_price)
+        .ok_or(MathError::MathOverflow)?;
+    Ok([swap_price, commision_amount])
+}
+
+/// Amounts taken from `max_x` and `max_y` at the current pool ratio and
+/// lp minted for them, `[x_amount, y_amount, new_lp]`. The first deposit
+/// takes both maximums and sets the ratio, it mints the curve invariant
+/// of which `MINIMUM_LIQUIDITY` is locked and not included in `new_lp`.
+pub fn deposit_define(
+    max_x: u64,
+    max_y: u64,
+    total_lp: u64,
+    token_x_in_pool: u64,
+    token_y_in_pool: u64,
+    curve: &CurveType,
+) -> Result<[u64; 3], MathError> {
+    if total_lp == 0 {
+        let liquidity = curve
+            .initial_liquidity(max_x, max_y)
+            .ok_or(MathError::MathOverflow)?;
+        if liquidity <= MINIMUM_LIQUIDITY {
+            return Err(MathError::ZeroLiquidity);
+        }
+        return Ok([max_x, max_y, liquidity - MINIMUM_LIQUIDITY]);
+    }
+
+    let new_lp = core::cmp::min(
+        math::mul_div_u64(max_x, total_lp, token_x_in_pool, Rounding::Down)
+            .ok_or(MathError::MathOverflow)?,
+        math::mul_div_u64(max_y, total_lp, token_y_in_pool, Rounding::Down)
+            .ok_or(MathError::MathOverflow)?,
+    );
+    if new_lp == 0 {
+        return Err(MathError::ZeroLiquidity);
+    }
+    Ok([
+        math::mul_div_u64(new_lp, token_x_in_pool, total_lp, Rounding::Up)
+            .ok_or(MathError::MathOverflow)?,
+        math::mul_div_u64(new_lp, token_y_in_pool, total_lp, Rounding::Up)
+            .ok_or(MathError::MathOverflow)?,
+        new_lp,
+    ])
+}
+
+/// Part of `amount` to swap, commision included, so the rest and the swap
+/// output keep the pool ratio. With `D = den`, `G = den + num` and
+/// `b = reserve * (G + D)` it is the positive root
+/// `s = 2 * amount * reserve * G / (b + sqrt(b^2 + 4 * D^2 * amount * reserve))`,
+/// rounded down. Exact for the constant product curve, on other curves it
+/// estimates the split and the part not fitting the ratio stays with the user.
+pub fn single_sided_swap_define(
+    amount: u64,
+    reserve_from: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Result<u64, MathError> {
+    let amount = U256::from(amount);
+    let reserve = U256::from(reserve_from);
+    let d = U256::from(fee_denominator);
+    let g = d
+        .checked_add(U256::from(fee_numerator))
+        .ok_or(MathError::MathOverflow)?;
+
+    let b = reserve.checked_mul(g + d).ok_or(MathError::MathOverflow)?;
+    let discriminant = b
+        .checked_mul(b)
+        .and_then(|b2| {
+            U256::from(4)
+                .checked_mul(d * d)?
+                .checked_mul(amount)?
+                .checked_mul(reserve)?
+                .checked_add(b2)
+        })
+        .ok_or(MathError::MathOverflow)?;
+    let denominator = b
+        .checked_add(discriminant.integer_sqrt())
+        .ok_or(MathError::MathOverflow)?;
+    if denominator.is_zero() {
+        return Err(MathError::ZeroProvide);
+    }
+    let numerator = U256::from(2)
+        .checked_mul(amount)
+        .and_then(|n| n.checked_mul(reserve))
+        .and_then(|n| n.checked_mul(g))
+        .ok_or(MathError::MathOverflow)?;
+    u64::try_from(numerator / denominator).map_err(|_| MathError::MathOverflow)
+}
+
+/// Share of the reserves of `amount` lp tokens, `[x_amount, y_amount]`,
+/// rounded down.
+pub fn liquidity_profit(
+    amount: u64,
+    total_lp: u64,
+    token_x_in_pool: u64,
+    token_y_in_pool: u64,
+) -> Result<[u64; 2], MathError> {
+    if total_lp == 0 {
+        return Ok([0, 0]);
+    }
+    Ok([
+        math::mul_div_u64(token_x_in_pool, amount, total_lp, Rounding::Down)
+            .ok_or(MathError::MathOverflow)?,
+        math::mul_div_u64(token_y_in_pool, amount, total_lp, Rounding::Down)
+            .ok_or(MathError::MathOverflow)?,
+    ])
+}
+
+/// Growth of commision per lp token, or per unit of concentrated
+/// liquidity, Q64.64, rounded down.
+pub fn fee_growth_define(commision_amount: u64, total_liquidity: u128) -> Result<u128, MathError> {
+    if total_liquidity == 0 {
+        return Ok(0);
+    }
+    math::mul_div(
+        commision_amount as u128,
+        math::Q64,
+        total_liquidity,
+        Rounding::Down,
+    )
+    .ok_or(MathError::MathOverflow)
+}
+
+/// Commision earned by `lp_amount` since `fee_growth_checkpoint`, rounded down.
+pub fn fee_profit(
+    lp_amount: u64,
+    fee_growth: u128,
+    fee_growth_checkpoint: u128,
+) -> Result<u64, MathError> {
+    let growth = fee_growth
+        .checked_sub(fee_growth_checkpoint)
+        .ok_or(MathError::MathOverflow)?;
+    math::to_u64(
+        math::mul_div(lp_amount as u128, growth, math::Q64, Rounding::Down)
+            .ok_or(MathError::MathOverflow)?,
+    )
+    .ok_or(MathError::MathOverflow)
+}
+
+/// Commision earned by `liquidity` since `fee_growth_inside_checkpoint`,
+/// rounded down. The growth inside a range wraps on overflow.
+pub fn position_fee_define(
+    liquidity: u128,
+    fee_growth_inside: u128,
+    fee_growth_inside_checkpoint: u128,
+) -> Result<u64, MathError> {
+    let growth = fee_growth_inside.wrapping_sub(fee_growth_inside_checkpoint);
+    math::to_u64(
+        math::mul_div(liquidity, growth, math::Q64, Rounding::Down)
+            .ok_or(MathError::MathOverflow)?,
+    )
+    .ok_or(MathError::MathOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(curve: CurveType, compound_fees: bool) -> PoolSnapshot {
+        PoolSnapshot {
+            curve,
+            fee_numerator: 3,
+            fee_denominator: 1000,
+            protocol_fee_numerator: 1,
+            protocol_fee_denominator: 5,
+            compound_fees,
+            reserve_x: 0,
+            reserve_y: 0,
+            total_lp: 0,
+            staked_lp: 0,
+            sqrt_price: math::Q64,
+            tick_current: 0,
+            liquidity: 0,
+            fee_growth_x: 0,
+            fee_growth_y: 0,
+            ticks: Ticks::default(),
+        }
+    }
+
+    #[test]
+    fn swaps_move_reserves_and_fee_growth() {
+        let mut snapshot = snapshot(CurveType::ConstantProduct, false);
+        assert_eq!(
+            snapshot.deposit(500000, 750000),
+            Ok([500000, 750000, 611372])
+        );
+        assert_eq!([snapshot.total_lp, snapshot.staked_lp], [612372, 611372]);
+
+        let result = snapshot.swap_exact_in(100000, true).unwrap();
+        assert_eq!(
+            result,
+            SwapResult {
+                swap_price: 99700,
+                commision_amount: 300,
+                amount_out: 124687,
+                split: CommisionSplit {
+                    compounded: 0,
+                    commision_account: 300,
+                    protocol_fee: 60,
+                    fee_growth: 7241448050761716,
+                },
+            }
+        );
+        assert_eq!(result.amount_in(), Ok(100000));
+        assert_eq!(result.pool_amount(), Ok(99700));
+
+        let result = snapshot.swap_exact_out(50000, true).unwrap();
+        assert_eq!([result.swap_price, result.commision_amount], [52120, 157]);
+        assert_eq!(result.split.protocol_fee, 31);
+        assert_eq!(result.split.fee_growth, 3801760226649901);
+
+        assert_eq!([snapshot.reserve_x, snapshot.reserve_y], [651820, 575313]);
+        assert_eq!(snapshot.fee_growth_x, 7241448050761716 + 3801760226649901);
+        assert_eq!(snapshot.fee_growth_y, 0);
+        assert_eq!(
+            snapshot.swap_exact_out(575313, true),
+            Err(MathError::OverBuy)
+        );
+
+        assert_eq!(snapshot.withdraw(611372), Ok([650755, 574373]));
+        assert_eq!(
+            [snapshot.reserve_x, snapshot.reserve_y, snapshot.total_lp],
+            [1065, 940, MINIMUM_LIQUIDITY]
+        );
+
+        // the locked lp earns nothing, the protocol collects the lp share
+        assert_eq!(snapshot.staked_lp, 0);
+        let result = snapshot.swap_exact_in(1003, true).unwrap();
+        assert_eq!(
+            result.split,
+            CommisionSplit {
+                compounded: 0,
+                commision_account: 3,
+                protocol_fee: 3,
+                fee_growth: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn compounding_keeps_lp_commision() {
+        let mut snapshot = snapshot(CurveType::ConstantProduct, true);
+        snapshot.deposit(500000, 750000).unwrap();
+
+        let result = snapshot.swap_exact_in(100000, true).unwrap();
+        assert_eq!(result.amount_out, 124687);
+        assert_eq!(
+            result.split,
+            CommisionSplit {
+                compounded: 240,
+                commision_account: 60,
+                protocol_fee: 60,
+                fee_growth: 0,
+            }
+        );
+        assert_eq!(result.pool_amount(), Ok(99940));
+        assert_eq!(snapshot.reserve_x, 599940);
+        assert_eq!(snapshot.fee_growth_x, 0);
+    }
+
+    #[test]
+    fn concentrated_split_sums_step_growth() {
+        let mut snapshot = snapshot(
+            CurveType::Concentrated {
+                tick_spacing: 60,
+                initial_sqrt_price: math::Q64,
+            },
+            false,
+        );
+        // the vaults hold the positions, not priced by them
+        snapshot.reserve_x = 1_000_000_000;
+        snapshot.reserve_y = 1_000_000_000;
+        // a wide and a narrow position, the swap crosses the narrow one
+        for (lower, upper, liquidity) in [(-600, 600, 1_000_000_000), (-60, 60, 4_000_000_000)] {
+            snapshot
+                .ticks
+                .update(lower, liquidity, false, 0, 0, 0)
+                .unwrap();
+            snapshot
+                .ticks
+                .update(upper, liquidity, true, 0, 0, 0)
+                .unwrap();
+            snapshot.liquidity += liquidity as u128;
+        }
+
+        let result = snapshot.swap_exact_in(20_000_000, true).unwrap();
+        assert!(snapshot.tick_current < -60);
+        assert_eq!(snapshot.liquidity, 1_000_000_000);
+        assert_eq!(result.split.commision_account, result.commision_amount);
+        assert_eq!(result.split.fee_growth, snapshot.fee_growth_x);
+        // the growth of the crossed range was per a larger liquidity
+        let lp_commision = result.commision_amount - result.split.protocol_fee;
+        assert!(result.split.fee_growth < fee_growth_define(lp_commision, 1_000_000_000).unwrap());
+        assert_eq!(snapshot.fee_growth_y, 0);
+    }
+}
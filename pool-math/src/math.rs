@@ -0,0 +1,244 @@
+//! Deterministic integer math used by the pool pricing.
+//!
+//! Every operation is checked and returns `None` on overflow or division by
+//! zero. Divisions take an explicit rounding direction, callers pick the one
+//! that favours the pool.
+
+mod uint_types {
+    #![allow(clippy::all)]
+    use uint::construct_uint;
+
+    construct_uint! {
+        pub struct U192(3);
+    }
+
+    construct_uint! {
+        pub struct U256(4);
+    }
+}
+
+pub use uint_types::{U192, U256};
+
+/// One in Q64.64 fixed point.
+pub const Q64: u128 = 1 << 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// `numerator / denominator` rounded in the given direction.
+pub fn checked_div(numerator: u128, denominator: u128, rounding: Rounding) -> Option<u128> {
+    let quotient = numerator.checked_div(denominator)?;
+    match rounding {
+        Rounding::Down => Some(quotient),
+        Rounding::Up if quotient * denominator == numerator => Some(quotient),
+        Rounding::Up => quotient.checked_add(1),
+    }
+}
+
+/// `a * b / c` with a 192-bit intermediate product.
+pub fn mul_div(a: u128, b: u128, c: u128, rounding: Rounding) -> Option<u128> {
+    if c == 0 {
+        return None;
+    }
+    let product = U192::from(a).checked_mul(U192::from(b))?;
+    let (quotient, remainder) = product.div_mod(U192::from(c));
+    let quotient = match rounding {
+        Rounding::Up if !remainder.is_zero() => quotient.checked_add(U192::one())?,
+        _ => quotient,
+    };
+    u128::try_from(quotient).ok()
+}
+
+/// `a * b / c` for token amounts, failing if the result does not fit `u64`.
+pub fn mul_div_u64(a: u64, b: u64, c: u64, rounding: Rounding) -> Option<u64> {
+    to_u64(mul_div(a as u128, b as u128, c as u128, rounding)?)
+}
+
+/// Integer square root rounded down.
+pub fn sqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    let mut x = 1u128 << (64 - value.leading_zeros() / 2);
+    loop {
+        let y = (x + value / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+/// Price of `reserve_base` in `reserve_quote`, Q64.64 rounded down. The
+/// reserves must not be zero.
+pub fn price_q64(reserve_base: u64, reserve_quote: u64) -> u128 {
+    ((reserve_quote as u128) << 64) / reserve_base as u128
+}
+
+/// Average Q64.64 price between two cumulative price readings taken
+/// `elapsed` seconds apart, the accumulator may have wrapped in between.
+pub fn twap(cumulative_start: u128, cumulative_end: u128, elapsed: u64) -> u128 {
+    cumulative_end.wrapping_sub(cumulative_start) / elapsed as u128
+}
+
+/// Natural logarithm of two in Q64.64.
+pub const LN2_Q64: u128 = 12786308645202655659;
+
+/// Relative error bound of `pow_q64` before rounding, as a right shift.
+const POW_ERROR_SHIFT: u32 = 40;
+
+/// Binary logarithm of a Q64.64 value, Q64.64 rounded down.
+pub fn log2_q64(value: u128) -> Option<i128> {
+    if value == 0 {
+        return None;
+    }
+    // integer part from the highest bit, then the value scaled into [1, 2)
+    // gives one fractional bit per squaring
+    let integer = 63 - value.leading_zeros() as i128;
+    let mut y = if integer >= 0 {
+        value >> integer
+    } else {
+        value << -integer
+    };
+    let mut result = integer << 64;
+    let mut bit = 1i128 << 63;
+    while bit > 0 {
+        y = mul_div(y, y, Q64, Rounding::Down)?;
+        if y >= 2 * Q64 {
+            y >>= 1;
+            result += bit;
+        }
+        bit >>= 1;
+    }
+    Some(result)
+}
+
+/// Natural logarithm of a Q64.64 value, Q64.64.
+pub fn ln_q64(value: u128) -> Option<i128> {
+    let log2 = log2_q64(value)?;
+    mul_div_signed(log2, LN2_Q64, Q64)
+}
+
+/// `e^value` of a Q64.64 value, Q64.64. `None` if it does not fit.
+pub fn exp_q64(value: i128) -> Option<u128> {
+    // value = n * ln(2) + r with 0 <= r < ln(2), e^r by its Taylor series
+    let n = value.div_euclid(LN2_Q64 as i128);
+    let r = value.rem_euclid(LN2_Q64 as i128) as u128;
+    let mut term = Q64;
+    let mut sum = Q64;
+    for k in 1..=32u128 {
+        term = mul_div(term, r, Q64 * k, Rounding::Down)?;
+        if term == 0 {
+            break;
+        }
+        sum += term;
+    }
+    if n > 62 {
+        None
+    } else if n >= 0 {
+        Some(sum << n)
+    } else if n > -128 {
+        Some(sum >> -n)
+    } else {
+        Some(0)
+    }
+}
+
+/// `base^(numerator / denominator)` of a Q64.64 value, Q64.64. The result
+/// is moved past the error bound of the approximation in the given direction.
+pub fn pow_q64(base: u128, numerator: u64, denominator: u64, rounding: Rounding) -> Option<u128> {
+    if base == 0 {
+        return Some(0);
+    }
+    let exponent = mul_div_signed(ln_q64(base)?, numerator as u128, denominator as u128)?;
+    let raw = exp_q64(exponent)?;
+    let error = (raw >> POW_ERROR_SHIFT) + 1;
+    match rounding {
+        Rounding::Down => Some(raw.saturating_sub(error)),
+        Rounding::Up => raw.checked_add(error),
+    }
+}
+
+/// `a * b / c` for a signed `a`, rounded toward zero.
+fn mul_div_signed(a: i128, b: u128, c: u128) -> Option<i128> {
+    let magnitude = i128::try_from(mul_div(a.unsigned_abs(), b, c, Rounding::Down)?).ok()?;
+    Some(if a < 0 { -magnitude } else { magnitude })
+}
+
+pub fn to_u64(value: u128) -> Option<u64> {
+    u64::try_from(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reference values are the exact results, Q64.64 rounded down
+
+    #[test]
+    fn log2_q64_rounds_down() {
+        for (value, expected) in [
+            (1, -64 << 64),
+            (Q64 / 2, -(Q64 as i128)),
+            (Q64, 0),
+            (8 << 64, 3 << 64),
+            (3 << 64, 29237397617229858719),
+            (10 << 64, 61278757397652712441),
+            (Q64 / 3, -29237397617229858722),
+            (1000000 << 64, 367672544385916274646),
+            (4295048017, -590295310189611807533),
+            (12345678901234567890123, 173148966117817123421),
+            (u128::MAX, 1180591620717411303423),
+        ] {
+            let result = log2_q64(value).unwrap();
+            assert!(result <= expected && expected - result <= 1, "{}", value);
+        }
+        assert_eq!(log2_q64(0), None);
+    }
+
+    #[test]
+    fn exp_q64_reference() {
+        for (value, expected) in [
+            (0, Q64),
+            (Q64 as i128, 50143449209799256682),
+            (-(Q64 as i128), 6786177901268885274),
+            (Q64 as i128 / 2, 30413539329486470295),
+            (10 * Q64 as i128, 406316577365116946489258),
+            (-20 * Q64 as i128, 38021573369),
+        ] {
+            // far within the error bound taken by `pow_q64`
+            let result = exp_q64(value).unwrap();
+            assert!(
+                result.abs_diff(expected) <= (expected >> 60) + 8,
+                "{}",
+                value
+            );
+        }
+        assert_eq!(exp_q64(63 * LN2_Q64 as i128), None);
+        assert_eq!(exp_q64(-200 * Q64 as i128), Some(0));
+    }
+
+    #[test]
+    fn pow_q64_error_bound() {
+        for (base, numerator, denominator, expected) in [
+            (2 * Q64, 1, 2, 26087635650665564424),
+            (3 * Q64, 2, 5, 28626498143935559009),
+            (Q64 / 2, 3, 7, 13705878173351620644),
+            (1000 * Q64, 4, 5, 4633612614428078043356),
+            (5 * Q64, 3, 1, 125 * Q64),
+        ] {
+            let down = pow_q64(base, numerator, denominator, Rounding::Down).unwrap();
+            let up = pow_q64(base, numerator, denominator, Rounding::Up).unwrap();
+            // the approximation is well within the error bound, so the
+            // directed results bracket the exact power at about one bound
+            let raw = down / 2 + up / 2;
+            assert!(raw.abs_diff(expected) <= expected >> (POW_ERROR_SHIFT + 8));
+            assert!(down <= expected && expected <= up);
+            assert!(up - down <= (expected >> (POW_ERROR_SHIFT - 1)) + 4);
+        }
+        assert_eq!(pow_q64(0, 1, 2, Rounding::Up), Some(0));
+    }
+}
@@ -1,6 +1,8 @@
 use solana_program::program_error::ProgramError;
 use thiserror::Error;
 
+use crate::pool_math::MathError;
+
 #[derive(Error, Debug, Copy, Clone)]
 pub enum PoolError {
     #[error("Trying to provide liquidity without offer both tokens")]
@@ -9,9 +11,6 @@ pub enum PoolError {
     #[error("Trying to provide more tokens than possessed")]
     OverProvide,
 
-    #[error("Wrong ratio of providing tokens")]
-    SlippageFail,
-
     #[error("Trying to buy more tokens than present in the pool")]
     OverBuy,
 
@@ -26,6 +25,120 @@ pub enum PoolError {
 
     #[error("Wrong withdraw account")]
     WrongWithdraw,
+
+    #[error("Account does not belong to the pool")]
+    WrongPoolAccount,
+
+    #[error("Pool is already initialized")]
+    PoolAlreadyInitialized,
+
+    #[error("Math operation overflow")]
+    MathOverflow,
+
+    #[error("Swap amount exceeds the given slippage bound")]
+    SlippageExceeded,
+
+    #[error("Fee numerator must be less than a non-zero denominator")]
+    InvalidFee,
+
+    #[error("Pool compounds commision into its reserves")]
+    FeesCompounded,
+
+    #[error("Token program account is not the spl token program")]
+    InvalidTokenProgram,
+
+    #[error("Account is not owned by the token program")]
+    InvalidAccountOwner,
+
+    #[error("Token account mint does not match the pool")]
+    InvalidMint,
+
+    #[error("Token account is not owned by the signer")]
+    InvalidTokenOwner,
+
+    #[error("Account is not a pool vault")]
+    InvalidVault,
+
+    #[error("Account is not the pool lp mint")]
+    InvalidLpMint,
+
+    #[error("Account is not a pool commision or treasury account")]
+    InvalidFeeAccount,
+
+    #[error("Account is not the pool authority")]
+    InvalidAuthority,
+
+    #[error("Signer is not the pool admin")]
+    InvalidAdmin,
+
+    #[error("Account must be writable")]
+    AccountNotWritable,
+
+    #[error("Deposit mints zero lp tokens")]
+    ZeroLiquidity,
+
+    #[error("Account is not the pool locked liquidity account")]
+    InvalidLockedLiquidity,
+
+    #[error("Transaction deadline has passed")]
+    Expired,
+
+    #[error("Flash loan was not repaid with its fee")]
+    FlashLoanNotRepaid,
+
+    #[error("Observations account does not belong to the pool")]
+    InvalidObservations,
+
+    #[error("Observation window is empty or older than the stored history")]
+    InvalidObservationWindow,
+
+    #[error("Curve parameters are out of range")]
+    InvalidCurve,
+
+    #[error("Instruction is not supported by the pool curve")]
+    WrongCurve,
+
+    #[error("Ticks are out of range or not multiples of the tick spacing")]
+    InvalidTickRange,
+
+    #[error("Account is not the position of the user and tick range")]
+    InvalidPosition,
+
+    #[error("Ticks account does not belong to the pool")]
+    InvalidTicks,
+
+    #[error("Route has no hops or a hop does not swap the previous output")]
+    InvalidRoute,
+
+    #[error("Lp mint already has a supply")]
+    LpMintSupply,
+
+    #[error("Lp mint has a freeze authority")]
+    LpMintFreezeAuthority,
+
+    #[error("Pool token account is not empty")]
+    AccountNotEmpty,
+
+    #[error("Pool token account has a delegate")]
+    AccountDelegated,
+
+    #[error("Pool token account has a close authority")]
+    AccountCloseAuthority,
+
+    #[error("Account is not the pool staked liquidity account")]
+    InvalidStakedLiquidity,
+
+    #[error("Invalid system program")]
+    InvalidSystemProgram,
+
+    #[error("Observations account would grow by more than 10KB at once")]
+    ObservationsGrowthTooLarge,
+
+    #[error("Pool has the maximum number of initialized ticks")]
+    TooManyTicks,
+
+    #[error("Position liquidity is below the minimum")]
+    PositionTooSmall,
 }
 
 impl From<PoolError> for ProgramError {
@@ -33,3 +146,15 @@ impl From<PoolError> for ProgramError {
         ProgramError::Custom(e as u32)
     }
 }
+
+impl From<MathError> for PoolError {
+    fn from(e: MathError) -> Self {
+        match e {
+            MathError::MathOverflow => PoolError::MathOverflow,
+            MathError::OverBuy => PoolError::OverBuy,
+            MathError::ZeroProvide => PoolError::ZeroProvide,
+            MathError::ZeroLiquidity => PoolError::ZeroLiquidity,
+            MathError::InvalidTicks => PoolError::InvalidTicks,
+        }
+    }
+}
@@ -8,71 +8,435 @@ use solana_program::{
 use spl_token;
 
 use crate::{
+    curve::CurveType,
     id,
-    state::{TotalCommision, WithdrawedFee},
+    state::{get_ticks_pubkey, Observations, Pool, Position, WithdrawedFee},
 };
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
 pub enum PoolInstruction {
-    /// Provide liquidity.
+    /// Provide liquidity at the current pool ratio, taking at most `max_x`
+    /// and `max_y`. Fails if less than `min_lp_out` lp tokens are minted.
+    /// The first deposit also mints `MINIMUM_LIQUIDITY` to the locked account.
+    /// A pool not compounding its commision mints the lp tokens to the staked
+    /// lp account, staked by the user`s fee position, see `StakeLp`.
+    /// Fails once past `deadline`, if given.
     /// Accounts:
-    /// 0. `[signer]` user`s account
-    /// 1. `[]` user`s token x account
-    /// 2. `[]` user`s token y account
-    /// 3. `[]` user`s token lp account
-    /// 4. `[]` pool`s token x account
-    /// 5. `[]` pool`s token y account
-    /// 6. `[]` mint lp token account
-    /// 7. `[signer]` minter account
-    /// 8. `[]` token program account, PDA
-    ProvideLiquidity { x_amount: u64, y_amount: u64 },
+    /// 0. `[signer, writable]` user`s account, pays the rent of a new fee position
+    /// 1. `[writable]` pool account
+    /// 2. `[writable]` fee position of the user`s lp token account, PDA
+    /// 3. `[writable]` user`s token x account
+    /// 4. `[writable]` user`s token y account
+    /// 5. `[writable]` user`s token lp account
+    /// 6. `[writable]` pool`s token x account
+    /// 7. `[writable]` pool`s token y account
+    /// 8. `[writable]` mint lp token account
+    /// 9. `[writable]` commision token x account
+    /// 10. `[writable]` commision token y account
+    /// 11. `[]` pool authority account, PDA
+    /// 12. `[]` token program account
+    /// 13. `[]` Rent sysvar
+    /// 14. `[]` System program
+    /// 15. `[writable]` locked lp token account, PDA
+    /// 16. `[writable]` observations account, PDA
+    /// 17. `[writable]` staked lp token account, PDA
+    ProvideLiquidity {
+        max_x: u64,
+        max_y: u64,
+        min_lp_out: u64,
+        deadline: Option<Deadline>,
+    },
 
-    /// Swap tokens.
+    /// Swap tokens, buying exactly `amount` of the to token.
+    /// Fails if the price with commision is above `maximum_amount_in`
+    /// or once past `deadline`, if given.
     /// Accounts:
     /// 0. `[signer]` user`s account
-    /// 1. `[]` user`s token from swap account
-    /// 2. `[]` user`s token to swap account
-    /// 3. `[]` pool`s token from swap account
-    /// 4. `[]` pool`s token to swap account
-    /// 5. `[]` commision from account
-    /// 6. `[signer]` minter account
-    /// 7. `[]` token program account, PDA
-    SwapTokens { amount: u64 },
-
-    /// Withdraw liquidity.
+    /// 1. `[writable]` pool account
+    /// 2. `[writable]` user`s token from swap account
+    /// 3. `[writable]` user`s token to swap account
+    /// 4. `[writable]` pool`s token from swap account
+    /// 5. `[writable]` pool`s token to swap account
+    /// 6. `[writable]` commision from account
+    /// 7. `[]` pool authority account, PDA
+    /// 8. `[]` token program account
+    /// 9. `[]` mint lp token account
+    /// 10. `[writable]` observations account, PDA
+    /// 11. `[writable]` ticks account, PDA, crossed by concentrated liquidity pools
+    SwapTokens {
+        amount: u64,
+        maximum_amount_in: u64,
+        deadline: Option<Deadline>,
+    },
+
+    /// Withdraw liquidity, burning `amount` lp tokens staked by the user`s
+    /// fee position, or held by the user`s lp token account on a pool
+    /// compounding its commision.
+    /// Fails if less than `min_x_out` or `min_y_out` is returned
+    /// or once past `deadline`, if given.
+    /// Accounts: same as `ProvideLiquidity`.
+    WithdrawLiquidity {
+        amount: u64,
+        min_x_out: u64,
+        min_y_out: u64,
+        deadline: Option<Deadline>,
+    },
+
+    /// Withdraw the commision earned by the lp tokens staked by the fee
+    /// position of the user`s lp token account since its last checkpoint.
+    /// Accounts: 0 - 15 of `ProvideLiquidity`, with the pool account readonly.
+    WithdrawFee,
+
+    /// Initialize pool with a swap fee of `fee_numerator / fee_denominator`,
+    /// `protocol_fee_numerator / protocol_fee_denominator` of which is kept
+    /// for the treasury. With `compound_fees` the rest of the commision is
+    /// added to the pool reserves and `WithdrawFee` is not used.
+    /// Swaps are priced by `curve`, liquidity is always provided and
+    /// withdrawn at the pool ratio.
+    /// Pool`s token and commision accounts must be owned by the pool authority,
+    /// which also has to be the mint authority of the lp token. They must be
+    /// empty, with no delegate or close authority, and the lp mint must have
+    /// no supply and no freeze authority.
     /// Accounts:
-    /// 0. `[signer]` user`s account
-    /// 1. `[]` user`s token x account
-    /// 2. `[]` user`s token y account
-    /// 3. `[]` user`s token lp account
+    /// 0. `[signer, writable]` admin account
+    /// 1. `[writable]` pool account, PDA
+    /// 2. `[]` mint token x account
+    /// 3. `[]` mint token y account
     /// 4. `[]` pool`s token x account
     /// 5. `[]` pool`s token y account
     /// 6. `[]` mint lp token account
-    /// 7. `[signer]` minter account
-    /// 8. `[]` token program account, PDA
-    WithdrawLiquidity { amount: u64 },
+    /// 7. `[]` commision token x account
+    /// 8. `[]` commision token y account
+    /// 9. `[]` treasury token x account
+    /// 10. `[]` treasury token y account
+    /// 11. `[]` Rent sysvar
+    /// 12. `[]` System program
+    /// 13. `[writable]` locked lp token account, PDA
+    /// 14. `[]` token program account
+    /// 15. `[writable]` observations account, PDA
+    /// 16. `[writable]` staked lp token account, PDA
+    InitializePool {
+        fee_numerator: u64,
+        fee_denominator: u64,
+        protocol_fee_numerator: u64,
+        protocol_fee_denominator: u64,
+        compound_fees: bool,
+        curve: CurveType,
+    },
 
-    /// Withdraw fee.
+    /// Swap tokens, paying exactly `amount_in` of the from token, commision included.
+    /// Fails if less than `minimum_amount_out` of the to token is received
+    /// or once past `deadline`, if given.
+    /// Accounts: same as `SwapTokens`.
+    SwapExactIn {
+        amount_in: u64,
+        minimum_amount_out: u64,
+        deadline: Option<Deadline>,
+    },
+
+    /// Collect the protocol share of commision to the treasury accounts.
     /// Accounts:
-    /// 0. `[signer]` user`s account
-    /// 1. '[]' user`s withdraw info account
-    /// 2. `[]` user`s token x account
-    /// 3. `[]` user`s token y account
-    /// 4. `[]` user`s token lp account
+    /// 0. `[signer]` admin account
+    /// 1. `[writable]` pool account
+    /// 2. `[writable]` commision token x account
+    /// 3. `[writable]` commision token y account
+    /// 4. `[writable]` treasury token x account
+    /// 5. `[writable]` treasury token y account
+    /// 6. `[]` pool authority account, PDA
+    /// 7. `[]` token program account
+    CollectProtocolFees,
+
+    /// Provide liquidity with `amount` of the `token` mint only. The part
+    /// keeping the pool ratio after the swap is swapped with the usual
+    /// commision and the result is provided as `ProvideLiquidity`. On curves
+    /// other than constant product the split is estimated and the part not
+    /// fitting the pool ratio is left with the user.
+    /// Fails if less than `min_lp_out` lp tokens are minted
+    /// or once past `deadline`, if given.
+    /// Accounts: same as `ProvideLiquidity`.
+    ProvideSingleSided {
+        token: Pubkey,
+        amount: u64,
+        min_lp_out: u64,
+        deadline: Option<Deadline>,
+    },
+
+    /// Withdraw liquidity, burning `lp_amount` lp tokens, and swap the other
+    /// half with the usual commision so it is paid out in the `token` mint only.
+    /// Fails if less than `min_out` of the token is returned
+    /// or once past `deadline`, if given.
+    /// Accounts: same as `ProvideLiquidity`.
+    WithdrawSingleSided {
+        lp_amount: u64,
+        token: Pubkey,
+        min_out: u64,
+        deadline: Option<Deadline>,
+    },
+
+    /// Lend `amount` of the `token` mint and invoke the borrower program with
+    /// `callback_data` and the remaining accounts. The borrower has to return
+    /// `amount` and the swap commision on it to the pool vault before the
+    /// call ends, the commision is credited to liquidity providers as on a swap.
+    /// Accounts:
+    /// 0. `[writable]` pool account
+    /// 1. `[writable]` pool`s token account of the `token` mint
+    /// 2. `[]` pool`s token account of the other mint
+    /// 3. `[writable]` commision account of the `token` mint
+    /// 4. `[writable]` borrower`s token account receiving the loan
     /// 5. `[]` mint lp token account
-    /// 6. '[]' commision token x account
-    /// 7. '[]' commision token y account
-    /// 8. `[signer]` minter account
-    /// 9. `[]` token program account, PDA
-    /// 10. `[]` Rent sysvar, PDA
-    /// 11. `[]` System program, PDA
-    WithdrawFee,
+    /// 6. `[writable]` observations account, PDA
+    /// 7. `[]` pool authority account, PDA
+    /// 8. `[]` token program account
+    /// 9. `[]` borrower program
+    /// 10. ... accounts passed to the borrower program
+    FlashLoan {
+        token: Pubkey,
+        amount: u64,
+        callback_data: Vec<u8>,
+    },
+
+    /// Grow the observations ring buffer to `capacity` observations, the
+    /// payer funds the rent. Smaller capacities are ignored.
+    /// An account grows by at most 10KB (`MAX_PERMITTED_DATA_INCREASE`) per
+    /// instruction, larger capacities fail and must be reached over several
+    /// instructions.
+    /// Accounts:
+    /// 0. `[signer, writable]` payer account
+    /// 1. `[]` pool account
+    /// 2. `[writable]` observations account, PDA
+    /// 3. `[]` System program
+    GrowObservations { capacity: u16 },
+
+    /// Time weighted average prices and lp supply over the last
+    /// `seconds_ago` seconds, returned as `ObservedTwap` return data.
+    /// Fails if the window starts before the oldest stored observation.
+    /// Accounts:
+    /// 0. `[]` pool account
+    /// 1. `[]` observations account, PDA
+    /// 2. `[]` pool`s token x account
+    /// 3. `[]` pool`s token y account
+    /// 4. `[]` mint lp token account
+    ObserveTwap { seconds_ago: u32 },
+
+    /// Provide liquidity to a concentrated liquidity pool between
+    /// `tick_lower` and `tick_upper`, taking at most `max_x` and `max_y`.
+    /// Creates the user`s position for the range if needed and pays out the
+    /// commision it has earned. Fails if less than `min_liquidity` is added,
+    /// if the position would hold less than `MIN_POSITION_LIQUIDITY`, if the
+    /// pool would have more than `MAX_TICKS` initialized ticks or once past
+    /// `deadline`, if given.
+    /// Accounts:
+    /// 0. `[signer, writable]` user`s account, pays for new position and ticks
+    /// 1. `[writable]` pool account
+    /// 2. `[writable]` position account, PDA
+    /// 3. `[writable]` ticks account, PDA
+    /// 4. `[writable]` user`s token x account
+    /// 5. `[writable]` user`s token y account
+    /// 6. `[writable]` pool`s token x account
+    /// 7. `[writable]` pool`s token y account
+    /// 8. `[writable]` commision token x account
+    /// 9. `[writable]` commision token y account
+    /// 10. `[]` pool authority account, PDA
+    /// 11. `[]` token program account
+    /// 12. `[]` System program
+    /// 13. `[writable]` observations account, PDA
+    ProvideConcentrated {
+        tick_lower: i32,
+        tick_upper: i32,
+        max_x: u64,
+        max_y: u64,
+        min_liquidity: u128,
+        deadline: Option<Deadline>,
+    },
+
+    /// Withdraw `liquidity` from the user`s concentrated liquidity position
+    /// between `tick_lower` and `tick_upper`, paying out the commision it
+    /// has earned. Zero `liquidity` only collects the commision.
+    /// Fails if less than `min_x_out` or `min_y_out` is returned, if the
+    /// position would keep less than `MIN_POSITION_LIQUIDITY`
+    /// or once past `deadline`, if given.
+    /// Accounts: same as `ProvideConcentrated`.
+    WithdrawConcentrated {
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        min_x_out: u64,
+        min_y_out: u64,
+        deadline: Option<Deadline>,
+    },
+
+    /// Swap exactly `amount_in` through `hops` pools, each hop paying in the
+    /// whole output of the previous one, commision included.
+    /// Fails if less than `min_out` of the last to token is received
+    /// or once past `deadline`, if given.
+    /// Accounts: `hops` groups of the 12 `SwapTokens` accounts, the from user
+    /// token account of every hop after the first must be the to user token
+    /// account of the previous hop. Every group ends with the ticks account
+    /// of its pool, required even if the pool is not a concentrated liquidity
+    /// one, so the hops are split at fixed 12 account boundaries.
+    RouteSwap {
+        amount_in: u64,
+        min_out: u64,
+        hops: u8,
+        deadline: Option<Deadline>,
+    },
+
+    /// Quote of a swap of `amount`, paid in if `exact_in` or bought
+    /// otherwise, returned as `SwapQuote` return data. Transfers nothing.
+    /// Accounts:
+    /// 0. `[]` pool account
+    /// 1. `[]` pool`s from token account
+    /// 2. `[]` pool`s to token account
+    /// 3. `[]` ticks account, PDA, walked by concentrated liquidity pools
+    QuoteSwap { amount: u64, exact_in: bool },
+
+    /// Quote of `ProvideLiquidity` taking at most `max_x` and `max_y`,
+    /// returned as `DepositQuote` return data. Transfers nothing.
+    /// Accounts:
+    /// 0. `[]` pool account
+    /// 1. `[]` pool`s token x account
+    /// 2. `[]` pool`s token y account
+    /// 3. `[]` mint lp token account
+    QuoteDeposit { max_x: u64, max_y: u64 },
+
+    /// Quote of `WithdrawLiquidity` burning `amount` lp tokens, returned as
+    /// `WithdrawQuote` return data. Transfers nothing.
+    /// Accounts: same as `QuoteDeposit`.
+    QuoteWithdraw { amount: u64 },
+
+    /// Stake `amount` lp tokens of the user`s lp token account in its fee
+    /// position, paying out the commision the position has earned. Only
+    /// staked lp tokens earn commision. Fails on a pool compounding its
+    /// commision.
+    /// Accounts: same as `ProvideLiquidity`.
+    StakeLp { amount: u64 },
+
+    /// Unstake `amount` lp tokens of the fee position to the user`s lp token
+    /// account, paying out the commision the position has earned. Unstaked
+    /// lp tokens can be transferred but earn no commision.
+    /// Accounts: same as `ProvideLiquidity`.
+    UnstakeLp { amount: u64 },
+}
+
+/// Last moment a trading instruction can be executed at, checked against the
+/// `Clock` sysvar.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deadline {
+    /// Cluster unix timestamp, in seconds.
+    Timestamp(i64),
+    Slot(u64),
+}
+
+/// Accounts of a single pool of a `RouteSwap`, swapping from the
+/// `from_user_token` to the `to_user_token` mint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapHop {
+    pub pool: Pubkey,
+    pub from_user_token: Pubkey,
+    pub to_user_token: Pubkey,
+    pub pool_from_token: Pubkey,
+    pub pool_to_token: Pubkey,
+    pub commision_from_token: Pubkey,
+    pub mint_lp_token: Pubkey,
+}
+
+/// Return data of `ObserveTwap`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObservedTwap {
+    /// Average price of x in y, Q64.64.
+    pub price_x: u128,
+    /// Average price of y in x, Q64.64.
+    pub price_y: u128,
+    /// Average lp supply.
+    pub liquidity: u128,
+}
+
+/// Return data of `QuoteSwap`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SwapQuote {
+    /// Amount of the from token paid, commision included.
+    pub amount_in: u64,
+    /// Amount of the to token received.
+    pub amount_out: u64,
+    /// Amount of the from token paid to the pool curve.
+    pub swap_price: u64,
+    /// Commision of the from token.
+    pub commision_amount: u64,
+}
+
+/// Return data of `QuoteDeposit`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DepositQuote {
+    pub x_amount: u64,
+    pub y_amount: u64,
+    /// Lp tokens minted to the user.
+    pub lp_out: u64,
+}
+
+/// Return data of `QuoteWithdraw`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WithdrawQuote {
+    pub x_amount: u64,
+    pub y_amount: u64,
 }
 
 impl PoolInstruction {
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_pool(
+        admin: &Pubkey,
+        mint_x: &Pubkey,
+        mint_y: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        treasury_x_token: &Pubkey,
+        treasury_y_token: &Pubkey,
+        fee_numerator: u64,
+        fee_denominator: u64,
+        protocol_fee_numerator: u64,
+        protocol_fee_denominator: u64,
+        compound_fees: bool,
+        curve: CurveType,
+    ) -> Instruction {
+        let pool_pubkey = Pool::get_pool_pubkey(mint_x, mint_y);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::InitializePool {
+                fee_numerator,
+                fee_denominator,
+                protocol_fee_numerator,
+                protocol_fee_denominator,
+                compound_fees,
+                curve,
+            },
+            vec![
+                AccountMeta::new(*admin, true),
+                AccountMeta::new(pool_pubkey, false),
+                AccountMeta::new_readonly(*mint_x, false),
+                AccountMeta::new_readonly(*mint_y, false),
+                AccountMeta::new_readonly(*pool_x_token, false),
+                AccountMeta::new_readonly(*pool_y_token, false),
+                AccountMeta::new_readonly(*mint_lp_token, false),
+                AccountMeta::new_readonly(*commision_x_token, false),
+                AccountMeta::new_readonly(*commision_y_token, false),
+                AccountMeta::new_readonly(*treasury_x_token, false),
+                AccountMeta::new_readonly(*treasury_y_token, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(&pool_pubkey), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new(Observations::get_observations_pubkey(&pool_pubkey), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(&pool_pubkey), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn provide_liquidity(
         user: &Pubkey,
-        admin: &Pubkey,
+        pool: &Pubkey,
         x_user_token: &Pubkey,
         y_user_token: &Pubkey,
         lp_user_token: &Pubkey,
@@ -81,16 +445,24 @@ impl PoolInstruction {
         mint_lp_token: &Pubkey,
         commision_x_token: &Pubkey,
         commision_y_token: &Pubkey,
-        x_amount: u64,
-        y_amount: u64,
+        max_x: u64,
+        max_y: u64,
+        min_lp_out: u64,
+        deadline: Option<Deadline>,
     ) -> Instruction {
-        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(user);
-        let total_pubkey = TotalCommision::get_total_pubkey();
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
         Instruction::new_with_borsh(
             id(),
-            &PoolInstruction::ProvideLiquidity { x_amount, y_amount },
+            &PoolInstruction::ProvideLiquidity {
+                max_x,
+                max_y,
+                min_lp_out,
+                deadline,
+            },
             vec![
-                AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
                 AccountMeta::new(withdraw_pubkey, false),
                 AccountMeta::new(*x_user_token, false),
                 AccountMeta::new(*y_user_token, false),
@@ -100,18 +472,21 @@ impl PoolInstruction {
                 AccountMeta::new(*mint_lp_token, false),
                 AccountMeta::new(*commision_x_token, false),
                 AccountMeta::new(*commision_y_token, false),
-                AccountMeta::new(total_pubkey, false),
-                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new_readonly(authority_pubkey, false),
                 AccountMeta::new_readonly(spl_token::id(), false),
                 AccountMeta::new_readonly(sysvar::rent::id(), false),
                 AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(pool), false),
             ],
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn withdraw_liquidity(
         user: &Pubkey,
-        admin: &Pubkey,
+        pool: &Pubkey,
         x_user_token: &Pubkey,
         y_user_token: &Pubkey,
         lp_user_token: &Pubkey,
@@ -121,14 +496,23 @@ impl PoolInstruction {
         commision_x_token: &Pubkey,
         commision_y_token: &Pubkey,
         amount: u64,
+        min_x_out: u64,
+        min_y_out: u64,
+        deadline: Option<Deadline>,
     ) -> Instruction {
-        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(user);
-        let total_pubkey = TotalCommision::get_total_pubkey();
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
         Instruction::new_with_borsh(
             id(),
-            &PoolInstruction::WithdrawLiquidity { amount },
+            &PoolInstruction::WithdrawLiquidity {
+                amount,
+                min_x_out,
+                min_y_out,
+                deadline,
+            },
             vec![
-                AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
                 AccountMeta::new(withdraw_pubkey, false),
                 AccountMeta::new(*x_user_token, false),
                 AccountMeta::new(*y_user_token, false),
@@ -138,44 +522,99 @@ impl PoolInstruction {
                 AccountMeta::new(*mint_lp_token, false),
                 AccountMeta::new(*commision_x_token, false),
                 AccountMeta::new(*commision_y_token, false),
-                AccountMeta::new(total_pubkey, false),
-                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new_readonly(authority_pubkey, false),
                 AccountMeta::new_readonly(spl_token::id(), false),
                 AccountMeta::new_readonly(sysvar::rent::id(), false),
                 AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(pool), false),
             ],
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn swap_tokens(
         user: &Pubkey,
-        admin: &Pubkey,
+        pool: &Pubkey,
         from_user_token: &Pubkey,
         to_user_token: &Pubkey,
         pool_from_token: &Pubkey,
         pool_to_token: &Pubkey,
         commision_from_token: &Pubkey,
+        mint_lp_token: &Pubkey,
         amount: u64,
+        maximum_amount_in: u64,
+        deadline: Option<Deadline>,
     ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
         Instruction::new_with_borsh(
             id(),
-            &PoolInstruction::SwapTokens { amount },
+            &PoolInstruction::SwapTokens {
+                amount,
+                maximum_amount_in,
+                deadline,
+            },
             vec![
                 AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*pool, false),
                 AccountMeta::new(*from_user_token, false),
                 AccountMeta::new(*to_user_token, false),
                 AccountMeta::new(*pool_from_token, false),
                 AccountMeta::new(*pool_to_token, false),
                 AccountMeta::new(*commision_from_token, false),
-                AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(*mint_lp_token, false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(get_ticks_pubkey(pool), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn swap_exact_in(
+        user: &Pubkey,
+        pool: &Pubkey,
+        from_user_token: &Pubkey,
+        to_user_token: &Pubkey,
+        pool_from_token: &Pubkey,
+        pool_to_token: &Pubkey,
+        commision_from_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        deadline: Option<Deadline>,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::SwapExactIn {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            },
+            vec![
+                AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(*from_user_token, false),
+                AccountMeta::new(*to_user_token, false),
+                AccountMeta::new(*pool_from_token, false),
+                AccountMeta::new(*pool_to_token, false),
+                AccountMeta::new(*commision_from_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
                 AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(*mint_lp_token, false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(get_ticks_pubkey(pool), false),
             ],
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn withdraw_fee(
         user: &Pubkey,
-        admin: &Pubkey,
+        pool: &Pubkey,
         x_user_token: &Pubkey,
         y_user_token: &Pubkey,
         lp_user_token: &Pubkey,
@@ -185,13 +624,14 @@ impl PoolInstruction {
         commision_x_token: &Pubkey,
         commision_y_token: &Pubkey,
     ) -> Instruction {
-        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(user);
-        let total_pubkey = TotalCommision::get_total_pubkey();
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
         Instruction::new_with_borsh(
             id(),
             &PoolInstruction::WithdrawFee,
             vec![
-                AccountMeta::new_readonly(*user, true),
+                AccountMeta::new(*user, true),
+                AccountMeta::new_readonly(*pool, false),
                 AccountMeta::new(withdraw_pubkey, false),
                 AccountMeta::new(*x_user_token, false),
                 AccountMeta::new(*y_user_token, false),
@@ -201,11 +641,484 @@ impl PoolInstruction {
                 AccountMeta::new(*mint_lp_token, false),
                 AccountMeta::new(*commision_x_token, false),
                 AccountMeta::new(*commision_y_token, false),
-                AccountMeta::new(total_pubkey, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+            ],
+        )
+    }
+
+    pub fn collect_protocol_fees(
+        admin: &Pubkey,
+        pool: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        treasury_x_token: &Pubkey,
+        treasury_y_token: &Pubkey,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::CollectProtocolFees,
+            vec![
                 AccountMeta::new_readonly(*admin, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new(*treasury_x_token, false),
+                AccountMeta::new(*treasury_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn provide_single_sided(
+        user: &Pubkey,
+        pool: &Pubkey,
+        x_user_token: &Pubkey,
+        y_user_token: &Pubkey,
+        lp_user_token: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        token: &Pubkey,
+        amount: u64,
+        min_lp_out: u64,
+        deadline: Option<Deadline>,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::ProvideSingleSided {
+                token: *token,
+                amount,
+                min_lp_out,
+                deadline,
+            },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(withdraw_pubkey, false),
+                AccountMeta::new(*x_user_token, false),
+                AccountMeta::new(*y_user_token, false),
+                AccountMeta::new(*lp_user_token, false),
+                AccountMeta::new(*pool_x_token, false),
+                AccountMeta::new(*pool_y_token, false),
+                AccountMeta::new(*mint_lp_token, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(pool), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_single_sided(
+        user: &Pubkey,
+        pool: &Pubkey,
+        x_user_token: &Pubkey,
+        y_user_token: &Pubkey,
+        lp_user_token: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        lp_amount: u64,
+        token: &Pubkey,
+        min_out: u64,
+        deadline: Option<Deadline>,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::WithdrawSingleSided {
+                lp_amount,
+                token: *token,
+                min_out,
+                deadline,
+            },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(withdraw_pubkey, false),
+                AccountMeta::new(*x_user_token, false),
+                AccountMeta::new(*y_user_token, false),
+                AccountMeta::new(*lp_user_token, false),
+                AccountMeta::new(*pool_x_token, false),
+                AccountMeta::new(*pool_y_token, false),
+                AccountMeta::new(*mint_lp_token, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(pool), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn flash_loan(
+        pool: &Pubkey,
+        pool_token: &Pubkey,
+        pool_other_token: &Pubkey,
+        commision_token: &Pubkey,
+        borrower_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        borrower_program: &Pubkey,
+        token: &Pubkey,
+        amount: u64,
+        callback_data: Vec<u8>,
+        callback_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let mut accounts = vec![
+            AccountMeta::new(*pool, false),
+            AccountMeta::new(*pool_token, false),
+            AccountMeta::new_readonly(*pool_other_token, false),
+            AccountMeta::new(*commision_token, false),
+            AccountMeta::new(*borrower_token, false),
+            AccountMeta::new_readonly(*mint_lp_token, false),
+            AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+            AccountMeta::new_readonly(authority_pubkey, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(*borrower_program, false),
+        ];
+        accounts.extend(callback_accounts);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::FlashLoan {
+                token: *token,
+                amount,
+                callback_data,
+            },
+            accounts,
+        )
+    }
+
+    pub fn grow_observations(payer: &Pubkey, pool: &Pubkey, capacity: u16) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::GrowObservations { capacity },
+            vec![
+                AccountMeta::new(*payer, true),
+                AccountMeta::new_readonly(*pool, false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )
+    }
+
+    pub fn observe_twap(
+        pool: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        seconds_ago: u32,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::ObserveTwap { seconds_ago },
+            vec![
+                AccountMeta::new_readonly(*pool, false),
+                AccountMeta::new_readonly(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new_readonly(*pool_x_token, false),
+                AccountMeta::new_readonly(*pool_y_token, false),
+                AccountMeta::new_readonly(*mint_lp_token, false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn provide_concentrated(
+        user: &Pubkey,
+        pool: &Pubkey,
+        x_user_token: &Pubkey,
+        y_user_token: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        tick_lower: i32,
+        tick_upper: i32,
+        max_x: u64,
+        max_y: u64,
+        min_liquidity: u128,
+        deadline: Option<Deadline>,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let position_pubkey = Position::get_position_pubkey(pool, user, tick_lower, tick_upper);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::ProvideConcentrated {
+                tick_lower,
+                tick_upper,
+                max_x,
+                max_y,
+                min_liquidity,
+                deadline,
+            },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(position_pubkey, false),
+                AccountMeta::new(get_ticks_pubkey(pool), false),
+                AccountMeta::new(*x_user_token, false),
+                AccountMeta::new(*y_user_token, false),
+                AccountMeta::new(*pool_x_token, false),
+                AccountMeta::new(*pool_y_token, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_concentrated(
+        user: &Pubkey,
+        pool: &Pubkey,
+        x_user_token: &Pubkey,
+        y_user_token: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        min_x_out: u64,
+        min_y_out: u64,
+        deadline: Option<Deadline>,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let position_pubkey = Position::get_position_pubkey(pool, user, tick_lower, tick_upper);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::WithdrawConcentrated {
+                tick_lower,
+                tick_upper,
+                liquidity,
+                min_x_out,
+                min_y_out,
+                deadline,
+            },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(position_pubkey, false),
+                AccountMeta::new(get_ticks_pubkey(pool), false),
+                AccountMeta::new(*x_user_token, false),
+                AccountMeta::new(*y_user_token, false),
+                AccountMeta::new(*pool_x_token, false),
+                AccountMeta::new(*pool_y_token, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+            ],
+        )
+    }
+
+    pub fn route_swap(
+        user: &Pubkey,
+        hops: &[SwapHop],
+        amount_in: u64,
+        min_out: u64,
+        deadline: Option<Deadline>,
+    ) -> Instruction {
+        let accounts = hops
+            .iter()
+            .flat_map(|hop| {
+                Self::swap_exact_in(
+                    user,
+                    &hop.pool,
+                    &hop.from_user_token,
+                    &hop.to_user_token,
+                    &hop.pool_from_token,
+                    &hop.pool_to_token,
+                    &hop.commision_from_token,
+                    &hop.mint_lp_token,
+                    0,
+                    0,
+                    None,
+                )
+                .accounts
+            })
+            .collect();
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::RouteSwap {
+                amount_in,
+                min_out,
+                hops: hops.len() as u8,
+                deadline,
+            },
+            accounts,
+        )
+    }
+
+    pub fn quote_swap(
+        pool: &Pubkey,
+        pool_from_token: &Pubkey,
+        pool_to_token: &Pubkey,
+        amount: u64,
+        exact_in: bool,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::QuoteSwap { amount, exact_in },
+            vec![
+                AccountMeta::new_readonly(*pool, false),
+                AccountMeta::new_readonly(*pool_from_token, false),
+                AccountMeta::new_readonly(*pool_to_token, false),
+                AccountMeta::new_readonly(get_ticks_pubkey(pool), false),
+            ],
+        )
+    }
+
+    pub fn quote_deposit(
+        pool: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        max_x: u64,
+        max_y: u64,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::QuoteDeposit { max_x, max_y },
+            vec![
+                AccountMeta::new_readonly(*pool, false),
+                AccountMeta::new_readonly(*pool_x_token, false),
+                AccountMeta::new_readonly(*pool_y_token, false),
+                AccountMeta::new_readonly(*mint_lp_token, false),
+            ],
+        )
+    }
+
+    pub fn quote_withdraw(
+        pool: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::QuoteWithdraw { amount },
+            vec![
+                AccountMeta::new_readonly(*pool, false),
+                AccountMeta::new_readonly(*pool_x_token, false),
+                AccountMeta::new_readonly(*pool_y_token, false),
+                AccountMeta::new_readonly(*mint_lp_token, false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn stake_lp(
+        user: &Pubkey,
+        pool: &Pubkey,
+        x_user_token: &Pubkey,
+        y_user_token: &Pubkey,
+        lp_user_token: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::StakeLp { amount },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(withdraw_pubkey, false),
+                AccountMeta::new(*x_user_token, false),
+                AccountMeta::new(*y_user_token, false),
+                AccountMeta::new(*lp_user_token, false),
+                AccountMeta::new(*pool_x_token, false),
+                AccountMeta::new(*pool_y_token, false),
+                AccountMeta::new(*mint_lp_token, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(pool), false),
+            ],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn unstake_lp(
+        user: &Pubkey,
+        pool: &Pubkey,
+        x_user_token: &Pubkey,
+        y_user_token: &Pubkey,
+        lp_user_token: &Pubkey,
+        pool_x_token: &Pubkey,
+        pool_y_token: &Pubkey,
+        mint_lp_token: &Pubkey,
+        commision_x_token: &Pubkey,
+        commision_y_token: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        let authority_pubkey = Pool::get_authority_pubkey(pool);
+        let withdraw_pubkey = WithdrawedFee::get_withdraw_pubkey(pool, lp_user_token);
+        Instruction::new_with_borsh(
+            id(),
+            &PoolInstruction::UnstakeLp { amount },
+            vec![
+                AccountMeta::new(*user, true),
+                AccountMeta::new(*pool, false),
+                AccountMeta::new(withdraw_pubkey, false),
+                AccountMeta::new(*x_user_token, false),
+                AccountMeta::new(*y_user_token, false),
+                AccountMeta::new(*lp_user_token, false),
+                AccountMeta::new(*pool_x_token, false),
+                AccountMeta::new(*pool_y_token, false),
+                AccountMeta::new(*mint_lp_token, false),
+                AccountMeta::new(*commision_x_token, false),
+                AccountMeta::new(*commision_y_token, false),
+                AccountMeta::new_readonly(authority_pubkey, false),
                 AccountMeta::new_readonly(spl_token::id(), false),
                 AccountMeta::new_readonly(sysvar::rent::id(), false),
                 AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(Pool::get_locked_lp_pubkey(pool), false),
+                AccountMeta::new(Observations::get_observations_pubkey(pool), false),
+                AccountMeta::new(Pool::get_staked_lp_pubkey(pool), false),
             ],
         )
     }
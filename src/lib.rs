@@ -1,10 +1,22 @@
+//! Liquidity pool program. The plain-number pool math lives in the `no_std`
+//! `pool-math` crate and is re-exported here.
+
+pub use pool_math::{self, concentrated, curve, math, MINIMUM_LIQUIDITY};
+
 pub mod error;
 pub mod instruction;
 pub mod processor;
 pub mod state;
+pub mod validation;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
 
 pub const POOL_SEED: &str = "liquidity pool";
+pub const LOCKED_LP_SEED: &str = "locked liquidity";
+pub const STAKED_LP_SEED: &str = "staked liquidity";
+pub const OBSERVATIONS_SEED: &str = "observations";
+pub const TICKS_SEED: &str = "ticks";
+pub const POSITION_SEED: &str = "position";
+
 solana_program::declare_id!("78yZvMzqAFzSHJrLNVWfqLRFFQ5ZCGzNXB4PBxmp6z5Y");
@@ -1,43 +1,679 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::account_info::{next_account_info, AccountInfo};
-use solana_program::entrypoint::ProgramResult;
-use solana_program::program::invoke_signed;
+use solana_program::entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program::{invoke_signed, set_return_data};
+use solana_program::program_error::ProgramError;
+use solana_program::program_option::COption;
 use solana_program::pubkey::Pubkey;
-use solana_program::sysvar::{rent::Rent, Sysvar};
+use solana_program::sysvar::{clock::Clock, rent::Rent, Sysvar};
 use solana_program::{msg, program::invoke, program_pack::Pack, system_instruction};
 
+use crate::concentrated;
+use crate::curve::CurveType;
 use crate::error::PoolError;
-use crate::instruction::PoolInstruction;
-use crate::state::{TotalCommision, WithdrawedFee};
-use crate::{id, POOL_SEED};
+use crate::instruction::{
+    Deadline, DepositQuote, ObservedTwap, PoolInstruction, SwapQuote, WithdrawQuote,
+};
+use crate::math::{self, Rounding};
+use crate::pool_math::{self, PoolSnapshot, SwapResult};
+use crate::state::{
+    get_ticks_pubkey, get_ticks_pubkey_with_bump, Observations, Pool, Position, Ticks,
+    WithdrawedFee,
+};
+use crate::validation;
+use crate::{
+    id, LOCKED_LP_SEED, MINIMUM_LIQUIDITY, OBSERVATIONS_SEED, POOL_SEED, POSITION_SEED,
+    STAKED_LP_SEED, TICKS_SEED,
+};
 
 use spl_token::state::{Account, Mint};
 
-pub const COMMISION_PERCENT: u64 = 3;
-pub const SLIPPAGE_TOLERANCE: u64 = 1;
-
 pub struct Processor;
 
 impl Processor {
     pub fn process(_program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
         let instruction = PoolInstruction::try_from_slice(input)?;
         match instruction {
-            PoolInstruction::ProvideLiquidity { x_amount, y_amount } => {
-                Self::provide_liquidity(accounts, x_amount, y_amount)
+            PoolInstruction::ProvideLiquidity {
+                max_x,
+                max_y,
+                min_lp_out,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::provide_liquidity(accounts, max_x, max_y, min_lp_out)
+            }
+            PoolInstruction::SwapTokens {
+                amount,
+                maximum_amount_in,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::swap_tokens(accounts, amount, maximum_amount_in)
+            }
+            PoolInstruction::SwapExactIn {
+                amount_in,
+                minimum_amount_out,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::swap_exact_in(accounts, amount_in, minimum_amount_out)
+            }
+            PoolInstruction::RouteSwap {
+                amount_in,
+                min_out,
+                hops,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::route_swap(accounts, amount_in, min_out, hops)
             }
-            PoolInstruction::SwapTokens { amount } => Self::swap_tokens(accounts, amount),
-            PoolInstruction::WithdrawLiquidity { amount } => {
-                Self::withdraw_liquidity(accounts, amount)
+            PoolInstruction::QuoteSwap { amount, exact_in } => {
+                Self::quote_swap(accounts, amount, exact_in)
+            }
+            PoolInstruction::QuoteDeposit { max_x, max_y } => {
+                Self::quote_deposit(accounts, max_x, max_y)
+            }
+            PoolInstruction::QuoteWithdraw { amount } => Self::quote_withdraw(accounts, amount),
+            PoolInstruction::StakeLp { amount } => Self::stake_lp(accounts, amount),
+            PoolInstruction::UnstakeLp { amount } => Self::unstake_lp(accounts, amount),
+            PoolInstruction::WithdrawLiquidity {
+                amount,
+                min_x_out,
+                min_y_out,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::withdraw_liquidity(accounts, amount, min_x_out, min_y_out)
             }
             PoolInstruction::WithdrawFee => Self::withdraw_fee(accounts),
+            PoolInstruction::InitializePool {
+                fee_numerator,
+                fee_denominator,
+                protocol_fee_numerator,
+                protocol_fee_denominator,
+                compound_fees,
+                curve,
+            } => Self::initialize_pool(
+                accounts,
+                fee_numerator,
+                fee_denominator,
+                protocol_fee_numerator,
+                protocol_fee_denominator,
+                compound_fees,
+                curve,
+            ),
+            PoolInstruction::CollectProtocolFees => Self::collect_protocol_fees(accounts),
+            PoolInstruction::ProvideSingleSided {
+                token,
+                amount,
+                min_lp_out,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::provide_single_sided(accounts, token, amount, min_lp_out)
+            }
+            PoolInstruction::WithdrawSingleSided {
+                lp_amount,
+                token,
+                min_out,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::withdraw_single_sided(accounts, lp_amount, token, min_out)
+            }
+            PoolInstruction::FlashLoan {
+                token,
+                amount,
+                callback_data,
+            } => Self::flash_loan(accounts, token, amount, callback_data),
+            PoolInstruction::GrowObservations { capacity } => {
+                Self::grow_observations(accounts, capacity)
+            }
+            PoolInstruction::ObserveTwap { seconds_ago } => {
+                Self::observe_twap(accounts, seconds_ago)
+            }
+            PoolInstruction::ProvideConcentrated {
+                tick_lower,
+                tick_upper,
+                max_x,
+                max_y,
+                min_liquidity,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::provide_concentrated(
+                    accounts,
+                    tick_lower,
+                    tick_upper,
+                    max_x,
+                    max_y,
+                    min_liquidity,
+                )
+            }
+            PoolInstruction::WithdrawConcentrated {
+                tick_lower,
+                tick_upper,
+                liquidity,
+                min_x_out,
+                min_y_out,
+                deadline,
+            } => {
+                Self::check_deadline(deadline)?;
+                Self::withdraw_concentrated(
+                    accounts, tick_lower, tick_upper, liquidity, min_x_out, min_y_out,
+                )
+            }
         }
     }
 
-    fn provide_liquidity(accounts: &[AccountInfo], x_amount: u64, y_amount: u64) -> ProgramResult {
-        msg!("Providing liquidity");
+    /// Fails once the cluster unix timestamp or slot is past `deadline`.
+    fn check_deadline(deadline: Option<Deadline>) -> ProgramResult {
+        let expired = match deadline {
+            Some(Deadline::Timestamp(timestamp)) => Clock::get()?.unix_timestamp > timestamp,
+            Some(Deadline::Slot(slot)) => Clock::get()?.slot > slot,
+            None => false,
+        };
+        if expired {
+            return Err(PoolError::Expired.into());
+        }
+        Ok(())
+    }
+
+    fn initialize_pool(
+        accounts: &[AccountInfo],
+        fee_numerator: u64,
+        fee_denominator: u64,
+        protocol_fee_numerator: u64,
+        protocol_fee_denominator: u64,
+        compound_fees: bool,
+        curve: CurveType,
+    ) -> ProgramResult {
+        msg!("Initialize pool");
+
+        let acc_iter = &mut accounts.iter();
+        let admin_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let mint_x_info = next_account_info(acc_iter)?;
+        let mint_y_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let treasury_x_token_info = next_account_info(acc_iter)?;
+        let treasury_y_token_info = next_account_info(acc_iter)?;
+        let rent_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+        let locked_lp_token_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let staked_lp_token_info = next_account_info(acc_iter)?;
+
+        validation::check_signer(admin_info)?;
+        validation::check_writable(admin_info)?;
+        validation::check_writable(pool_info)?;
+        validation::check_writable(locked_lp_token_info)?;
+        validation::check_writable(observations_info)?;
+        validation::check_writable(staked_lp_token_info)?;
+        validation::check_token_program(token_info)?;
+
+        if fee_denominator == 0
+            || fee_numerator >= fee_denominator
+            || protocol_fee_denominator == 0
+            || protocol_fee_numerator > protocol_fee_denominator
+        {
+            return Err(PoolError::InvalidFee.into());
+        }
+
+        // concentrated liquidity positions are paid their commision directly
+        if !curve.is_valid() || (curve.is_concentrated() && compound_fees) {
+            return Err(PoolError::InvalidCurve.into());
+        }
+        let (sqrt_price, tick_current) = match curve {
+            CurveType::Concentrated {
+                initial_sqrt_price, ..
+            } => (
+                initial_sqrt_price,
+                concentrated::tick_at_sqrt_price(initial_sqrt_price)
+                    .ok_or(PoolError::InvalidCurve)?,
+            ),
+            _ => (0, 0),
+        };
+
+        let (pool_pubkey, bump_seed) =
+            Pool::get_pool_pubkey_with_bump(mint_x_info.key, mint_y_info.key);
+
+        if pool_pubkey != *pool_info.key || mint_x_info.key == mint_y_info.key {
+            return Err(PoolError::WrongPoolAccount.into());
+        }
+
+        if !pool_info.data_is_empty() {
+            return Err(PoolError::PoolAlreadyInitialized.into());
+        }
+
+        let (authority_pubkey, authority_bump_seed) =
+            Pool::get_authority_pubkey_with_bump(&pool_pubkey);
+
+        validation::check_mint(mint_x_info)?;
+        validation::check_mint(mint_y_info)?;
+
+        for (token_info, mint_info) in [
+            (pool_x_token_info, mint_x_info),
+            (pool_y_token_info, mint_y_info),
+            (commision_x_token_info, mint_x_info),
+            (commision_y_token_info, mint_y_info),
+        ] {
+            let token = validation::check_token_account(token_info, mint_info.key)?;
+            if token.owner != authority_pubkey {
+                return Err(PoolError::InvalidAuthority.into());
+            }
+            validation::check_new_pool_token_account(&token)?;
+        }
+
+        let mint_lp = validation::check_mint(mint_lp_token_info)?;
+        if mint_lp.mint_authority != COption::Some(authority_pubkey) {
+            return Err(PoolError::InvalidAuthority.into());
+        }
+        validation::check_new_lp_mint(&mint_lp)?;
+
+        validation::check_token_account(treasury_x_token_info, mint_x_info.key)?;
+        validation::check_token_account(treasury_y_token_info, mint_y_info.key)?;
+
+        let (locked_lp_pubkey, locked_lp_bump_seed) =
+            Pool::get_locked_lp_pubkey_with_bump(&pool_pubkey);
+
+        if locked_lp_pubkey != *locked_lp_token_info.key {
+            return Err(PoolError::InvalidLockedLiquidity.into());
+        }
+
+        let (staked_lp_pubkey, staked_lp_bump_seed) =
+            Pool::get_staked_lp_pubkey_with_bump(&pool_pubkey);
+
+        if staked_lp_pubkey != *staked_lp_token_info.key {
+            return Err(PoolError::InvalidStakedLiquidity.into());
+        }
+
+        let (observations_pubkey, observations_bump_seed) =
+            Observations::get_observations_pubkey_with_bump(&pool_pubkey);
+
+        if observations_pubkey != *observations_info.key {
+            return Err(PoolError::InvalidObservations.into());
+        }
+
+        let pool = Pool {
+            admin: *admin_info.key,
+            mint_x: *mint_x_info.key,
+            mint_y: *mint_y_info.key,
+            pool_x_token: *pool_x_token_info.key,
+            pool_y_token: *pool_y_token_info.key,
+            mint_lp: *mint_lp_token_info.key,
+            locked_lp_token: locked_lp_pubkey,
+            staked_lp_token: staked_lp_pubkey,
+            commision_x_token: *commision_x_token_info.key,
+            commision_y_token: *commision_y_token_info.key,
+            treasury_x_token: *treasury_x_token_info.key,
+            treasury_y_token: *treasury_y_token_info.key,
+            fee_numerator,
+            fee_denominator,
+            protocol_fee_numerator,
+            protocol_fee_denominator,
+            compound_fees,
+            curve,
+            sqrt_price,
+            tick_current,
+            liquidity: 0,
+            protocol_fee_x: 0,
+            protocol_fee_y: 0,
+            staked_lp: 0,
+            fee_growth_x: 0,
+            fee_growth_y: 0,
+            price_x_cumulative: 0,
+            price_y_cumulative: 0,
+            liquidity_cumulative: 0,
+            last_update_timestamp: Clock::get()?.unix_timestamp,
+            bump_seed,
+            authority_bump_seed,
+        };
+        let space = pool.try_to_vec()?.len();
+        let rent = &Rent::from_account_info(rent_info)?;
+        let signer_seeds: &[&[_]] = &[
+            &mint_x_info.key.to_bytes(),
+            &mint_y_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[bump_seed],
+        ];
+        Self::create_pda(
+            admin_info,
+            pool_info,
+            system_program_info,
+            rent,
+            space,
+            &id(),
+            signer_seeds,
+        )?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+
+        let locked_lp_seeds: &[&[_]] = &[
+            &pool_pubkey.to_bytes(),
+            LOCKED_LP_SEED.as_bytes(),
+            &[locked_lp_bump_seed],
+        ];
+        Self::create_pda(
+            admin_info,
+            locked_lp_token_info,
+            system_program_info,
+            rent,
+            Account::LEN,
+            token_info.key,
+            locked_lp_seeds,
+        )?;
+        invoke(
+            &spl_token::instruction::initialize_account(
+                token_info.key,
+                &locked_lp_pubkey,
+                mint_lp_token_info.key,
+                system_program_info.key,
+            )?,
+            &[
+                locked_lp_token_info.clone(),
+                mint_lp_token_info.clone(),
+                system_program_info.clone(),
+                rent_info.clone(),
+                token_info.clone(),
+            ],
+        )?;
+
+        let staked_lp_seeds: &[&[_]] = &[
+            &pool_pubkey.to_bytes(),
+            STAKED_LP_SEED.as_bytes(),
+            &[staked_lp_bump_seed],
+        ];
+        Self::create_pda(
+            admin_info,
+            staked_lp_token_info,
+            system_program_info,
+            rent,
+            Account::LEN,
+            token_info.key,
+            staked_lp_seeds,
+        )?;
+        invoke(
+            &spl_token::instruction::initialize_account2(
+                token_info.key,
+                &staked_lp_pubkey,
+                mint_lp_token_info.key,
+                &authority_pubkey,
+            )?,
+            &[
+                staked_lp_token_info.clone(),
+                mint_lp_token_info.clone(),
+                rent_info.clone(),
+                token_info.clone(),
+            ],
+        )?;
+
+        let observations = Observations {
+            index: 0,
+            cardinality: 1,
+            observations: vec![pool.observation()],
+        };
+        let space = observations.try_to_vec()?.len();
+        let observations_seeds: &[&[_]] = &[
+            &pool_pubkey.to_bytes(),
+            OBSERVATIONS_SEED.as_bytes(),
+            &[observations_bump_seed],
+        ];
+        Self::create_pda(
+            admin_info,
+            observations_info,
+            system_program_info,
+            rent,
+            space,
+            &id(),
+            observations_seeds,
+        )?;
+        observations.serialize(&mut &mut observations_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    fn load_pool(pool_info: &AccountInfo) -> Result<Pool, ProgramError> {
+        if *pool_info.owner != id() {
+            return Err(PoolError::WrongPoolAccount.into());
+        }
+        Ok(Pool::try_from_slice(&pool_info.data.borrow())?)
+    }
+
+    fn load_observations(
+        observations_info: &AccountInfo,
+        pool_info: &AccountInfo,
+    ) -> Result<Observations, ProgramError> {
+        if *observations_info.key != Observations::get_observations_pubkey(pool_info.key)
+            || *observations_info.owner != id()
+        {
+            return Err(PoolError::InvalidObservations.into());
+        }
+        Ok(Observations::try_from_slice(
+            &observations_info.data.borrow(),
+        )?)
+    }
+
+    /// Writes the pool accumulators to its observations ring buffer.
+    fn record_observation(
+        observations_info: &AccountInfo,
+        pool_info: &AccountInfo,
+        pool: &Pool,
+    ) -> ProgramResult {
+        validation::check_writable(observations_info)?;
+        let mut observations = Self::load_observations(observations_info, pool_info)?;
+        observations.write(pool.observation());
+        observations.serialize(&mut &mut observations_info.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    fn grow_observations(accounts: &[AccountInfo], capacity: u16) -> ProgramResult {
+        msg!("Grow observations");
+
+        let acc_iter = &mut accounts.iter();
+        let payer_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+
+        validation::check_signer(payer_info)?;
+        validation::check_writable(payer_info)?;
+        validation::check_writable(observations_info)?;
+        validation::check_system_program(system_program_info)?;
+        Self::load_pool(pool_info)?;
+        let mut observations = Self::load_observations(observations_info, pool_info)?;
+
+        if capacity as usize <= observations.observations.len() {
+            return Ok(());
+        }
+        observations
+            .observations
+            .resize(capacity as usize, Default::default());
+        let space = observations.try_to_vec()?.len();
+        if space.saturating_sub(observations_info.data_len()) > MAX_PERMITTED_DATA_INCREASE {
+            return Err(PoolError::ObservationsGrowthTooLarge.into());
+        }
+        let lamports = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(observations_info.lamports());
+        if lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, observations_info.key, lamports),
+                &[
+                    payer_info.clone(),
+                    observations_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        observations_info.realloc(space, false)?;
+        observations.serialize(&mut &mut observations_info.data.borrow_mut()[..])?;
+
+        Ok(())
+    }
+
+    fn observe_twap(accounts: &[AccountInfo], seconds_ago: u32) -> ProgramResult {
+        msg!("Observe twap");
+
+        let acc_iter = &mut accounts.iter();
+        let pool_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+
+        let pool = Self::load_pool(pool_info)?;
+        let observations = Self::load_observations(observations_info, pool_info)?;
+        validation::check_vault(pool_x_token_info, &pool.pool_x_token)?;
+        validation::check_vault(pool_y_token_info, &pool.pool_y_token)?;
+        validation::check_lp_mint(mint_lp_token_info, &pool)?;
+
+        let reserve_x = Account::unpack_from_slice(&pool_x_token_info.data.borrow())?.amount;
+        let reserve_y = Account::unpack_from_slice(&pool_y_token_info.data.borrow())?.amount;
+        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
+        let now = Clock::get()?.unix_timestamp;
+        let current = pool.observation_at(reserve_x, reserve_y, total_lp, now);
+        let start = observations
+            .observe(&current, now - seconds_ago as i64)
+            .ok_or(PoolError::InvalidObservationWindow)?;
+        let (price_x, price_y) = start
+            .twap(&current)
+            .ok_or(PoolError::InvalidObservationWindow)?;
+        let twap = ObservedTwap {
+            price_x,
+            price_y,
+            liquidity: math::twap(
+                start.liquidity_cumulative,
+                current.liquidity_cumulative,
+                seconds_ago as u64,
+            ),
+        };
+        set_return_data(&twap.try_to_vec()?);
+
+        Ok(())
+    }
+
+    fn quote_swap(accounts: &[AccountInfo], amount: u64, exact_in: bool) -> ProgramResult {
+        msg!("Quote swap");
+
+        let acc_iter = &mut accounts.iter();
+        let pool_info = next_account_info(acc_iter)?;
+        let pool_from_token_info = next_account_info(acc_iter)?;
+        let pool_to_token_info = next_account_info(acc_iter)?;
+        let ticks_info = acc_iter.next();
+
+        let pool = Self::load_pool(pool_info)?;
+        let x_to_y = *pool_from_token_info.key == pool.pool_x_token;
+        if x_to_y {
+            validation::check_vault(pool_to_token_info, &pool.pool_y_token)?;
+        } else {
+            validation::check_vault(pool_from_token_info, &pool.pool_y_token)?;
+            validation::check_vault(pool_to_token_info, &pool.pool_x_token)?;
+        }
+        let reserve_from = Account::unpack_from_slice(&pool_from_token_info.data.borrow())?.amount;
+        let reserve_to = Account::unpack_from_slice(&pool_to_token_info.data.borrow())?.amount;
+        let (reserve_x, reserve_y) = if x_to_y {
+            (reserve_from, reserve_to)
+        } else {
+            (reserve_to, reserve_from)
+        };
+
+        // the same pricing as the swaps, on a snapshot of the pool; the lp
+        // supply only moves the fee growth, not the price
+        let mut snapshot = pool.snapshot(reserve_x, reserve_y, 0);
+        if pool.curve.is_concentrated() {
+            let ticks_info = ticks_info.ok_or(ProgramError::NotEnoughAccountKeys)?;
+            snapshot.ticks = Self::load_ticks(ticks_info, pool_info)?;
+        }
+        let result = if exact_in {
+            snapshot
+                .swap_exact_in(amount, x_to_y)
+                .map_err(PoolError::from)?
+        } else {
+            snapshot
+                .swap_exact_out(amount, x_to_y)
+                .map_err(PoolError::from)?
+        };
+
+        let quote = SwapQuote {
+            amount_in: result.amount_in().map_err(PoolError::from)?,
+            amount_out: result.amount_out,
+            swap_price: result.swap_price,
+            commision_amount: result.commision_amount,
+        };
+        set_return_data(&quote.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Checks the pool accounts of `QuoteDeposit` and `QuoteWithdraw`,
+    /// returning the snapshot of the pool.
+    fn quote_liquidity_accounts(accounts: &[AccountInfo]) -> Result<PoolSnapshot, ProgramError> {
+        let acc_iter = &mut accounts.iter();
+        let pool_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+
+        let pool = Self::load_pool(pool_info)?;
+        if pool.curve.is_concentrated() {
+            return Err(PoolError::WrongCurve.into());
+        }
+        validation::check_vault(pool_x_token_info, &pool.pool_x_token)?;
+        validation::check_vault(pool_y_token_info, &pool.pool_y_token)?;
+        validation::check_lp_mint(mint_lp_token_info, &pool)?;
+
+        let reserve_x = Account::unpack_from_slice(&pool_x_token_info.data.borrow())?.amount;
+        let reserve_y = Account::unpack_from_slice(&pool_y_token_info.data.borrow())?.amount;
+        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
+        Ok(pool.snapshot(reserve_x, reserve_y, total_lp))
+    }
+
+    fn quote_deposit(accounts: &[AccountInfo], max_x: u64, max_y: u64) -> ProgramResult {
+        msg!("Quote deposit");
+
+        let [x_amount, y_amount, lp_out] = Self::quote_liquidity_accounts(accounts)?
+            .deposit(max_x, max_y)
+            .map_err(PoolError::from)?;
+        let quote = DepositQuote {
+            x_amount,
+            y_amount,
+            lp_out,
+        };
+        set_return_data(&quote.try_to_vec()?);
+
+        Ok(())
+    }
+
+    fn quote_withdraw(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+        msg!("Quote withdraw");
+
+        let [x_amount, y_amount] = Self::quote_liquidity_accounts(accounts)?
+            .withdraw(amount)
+            .map_err(PoolError::from)?;
+        let quote = WithdrawQuote { x_amount, y_amount };
+        set_return_data(&quote.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Checks the account layout shared by `ProvideLiquidity`,
+    /// `WithdrawLiquidity` and `WithdrawFee` against the pool. Concentrated
+    /// liquidity pools use positions instead of lp tokens.
+    fn validate_liquidity_accounts(accounts: &[AccountInfo], pool: &Pool) -> ProgramResult {
+        if pool.curve.is_concentrated() {
+            return Err(PoolError::WrongCurve.into());
+        }
 
         let acc_iter = &mut accounts.iter();
         let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
         let withdraw_info = next_account_info(acc_iter)?;
         let x_user_token_info = next_account_info(acc_iter)?;
         let y_user_token_info = next_account_info(acc_iter)?;
@@ -45,20 +681,103 @@ impl Processor {
         let pool_x_token_info = next_account_info(acc_iter)?;
         let pool_y_token_info = next_account_info(acc_iter)?;
         let mint_lp_token_info = next_account_info(acc_iter)?;
-        let current_comission_x_tokem_info = next_account_info(acc_iter)?;
-        let current_comission_y_tokem_info = next_account_info(acc_iter)?;
-        let total_commision_info = next_account_info(acc_iter)?;
-        let admin_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
         let token_info = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
+        let locked_lp_token_info = next_account_info(acc_iter)?;
+
+        validation::check_signer(user_info)?;
+        for account_info in [
+            withdraw_info,
+            x_user_token_info,
+            y_user_token_info,
+            xy_lp_user_info,
+            pool_x_token_info,
+            pool_y_token_info,
+            mint_lp_token_info,
+            commision_x_token_info,
+            commision_y_token_info,
+            locked_lp_token_info,
+        ] {
+            validation::check_writable(account_info)?;
+        }
 
-        if !user_info.is_signer {
-            return Err(PoolError::SignedRequired.into());
+        validation::check_token_account(x_user_token_info, &pool.mint_x)?;
+        validation::check_token_account(y_user_token_info, &pool.mint_y)?;
+        validation::check_user_token_account(xy_lp_user_info, &pool.mint_lp, user_info.key)?;
+        validation::check_vault(pool_x_token_info, &pool.pool_x_token)?;
+        validation::check_vault(pool_y_token_info, &pool.pool_y_token)?;
+        validation::check_lp_mint(mint_lp_token_info, pool)?;
+        validation::check_fee_account(commision_x_token_info, &pool.commision_x_token)?;
+        validation::check_fee_account(commision_y_token_info, &pool.commision_y_token)?;
+        validation::check_authority(authority_info, pool_info.key)?;
+        validation::check_token_program(token_info)?;
+
+        if *locked_lp_token_info.key != pool.locked_lp_token {
+            return Err(PoolError::InvalidLockedLiquidity.into());
         }
 
-        if x_amount == 0 || y_amount == 0 {
-            return Err(PoolError::ZeroProvide.into());
+        Ok(())
+    }
+
+    fn provide_liquidity(
+        accounts: &[AccountInfo],
+        max_x: u64,
+        max_y: u64,
+        min_lp_out: u64,
+    ) -> ProgramResult {
+        msg!("Providing liquidity");
+
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let withdraw_info = next_account_info(acc_iter)?;
+        let x_user_token_info = next_account_info(acc_iter)?;
+        let y_user_token_info = next_account_info(acc_iter)?;
+        let xy_lp_user_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let locked_lp_token_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let staked_lp_token_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::load_pool(pool_info)?;
+        Self::validate_liquidity_accounts(accounts, &pool)?;
+        validation::check_writable(pool_info)?;
+        validation::check_writable(staked_lp_token_info)?;
+        validation::check_staked_lp(staked_lp_token_info, &pool)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
+
+        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
+        let token_x_in_pool = Account::unpack_from_slice(&pool_x_token_info.data.borrow())?.amount;
+        let token_y_in_pool = Account::unpack_from_slice(&pool_y_token_info.data.borrow())?.amount;
+        let [x_amount, y_amount, new_lp] = pool
+            .snapshot(token_x_in_pool, token_y_in_pool, total_lp)
+            .deposit(max_x, max_y)
+            .map_err(PoolError::from)?;
+        pool.update_cumulatives(
+            token_x_in_pool,
+            token_y_in_pool,
+            total_lp,
+            Clock::get()?.unix_timestamp,
+        );
+
+        if new_lp < min_lp_out {
+            return Err(PoolError::SlippageExceeded.into());
         }
 
         let x_user_token = Account::unpack_from_slice(&x_user_token_info.data.borrow())?.amount;
@@ -68,7 +787,9 @@ impl Processor {
             return Err(PoolError::OverProvide.into());
         }
 
-        Self::withdraw_fee(accounts)?;
+        if !pool.compound_fees {
+            Self::withdraw_fee(accounts)?;
+        }
 
         let ix = spl_token::instruction::transfer(
             token_info.key,
@@ -106,182 +827,626 @@ impl Processor {
             ],
         )?;
 
-        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?;
-        let pool_x_token = Account::unpack_from_slice(&pool_x_token_info.data.borrow())?;
-        let pool_y_token = Account::unpack_from_slice(&pool_y_token_info.data.borrow())?;
+        msg!("mint!!!!!!!!!!!!!!!!!!!!!!");
 
-        let new_lp: u64 = if total_lp.supply == 0 {
-            ((x_amount as f64) * (y_amount as f64)).sqrt() as u64
+        // commision is only earned by lp tokens staked in the pool
+        let lp_destination_info = if pool.compound_fees {
+            xy_lp_user_info
         } else {
-            if !Self::slippage_tolerance_check(pool_x_token, pool_y_token, x_amount, y_amount) {
-                return Err(PoolError::SlippageFail.into());
-            }
-            std::cmp::min(
-                x_amount * total_lp.supply / (pool_x_token.amount - x_amount),
-                y_amount * total_lp.supply / (pool_y_token.amount - y_amount),
-            )
+            staked_lp_token_info
         };
-
-        msg!("mint!!!!!!!!!!!!!!!!!!!!!!");
-
         let ilp = spl_token::instruction::mint_to(
             token_info.key,
             mint_lp_token_info.key,
-            xy_lp_user_info.key,
-            admin_info.key,
-            &[admin_info.key],
+            lp_destination_info.key,
+            authority_info.key,
+            &[authority_info.key],
             new_lp,
         )?;
-        invoke(
+        invoke_signed(
             &ilp,
             &[
                 mint_lp_token_info.clone(),
-                xy_lp_user_info.clone(),
-                admin_info.clone(),
+                lp_destination_info.clone(),
+                authority_info.clone(),
                 token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
 
-        let total_commision = TotalCommision::try_from_slice(&total_commision_info.data.borrow())?;
-        let token_x_commision =
-            Account::unpack_from_slice(&current_comission_x_tokem_info.data.borrow())?.amount;
-        let token_y_commision =
-            Account::unpack_from_slice(&current_comission_y_tokem_info.data.borrow())?.amount;
-        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
-        let user_lp = Account::unpack_from_slice(&xy_lp_user_info.data.borrow())?.amount;
-
-        let [x_amount, y_amount] = Self::liquidity_profit(
-            user_lp,
-            total_lp,
-            token_x_commision + total_commision.total_x_commision,
-            token_y_commision + total_commision.total_y_commision,
-        );
-
-        let mut withdraw = WithdrawedFee::try_from_slice(&withdraw_info.data.borrow())?;
-
-        withdraw.user_x_withdraw = x_amount;
-        withdraw.user_y_withdraw = y_amount;
+        if total_lp == 0 {
+            let ilocked = spl_token::instruction::mint_to(
+                token_info.key,
+                mint_lp_token_info.key,
+                locked_lp_token_info.key,
+                authority_info.key,
+                &[authority_info.key],
+                MINIMUM_LIQUIDITY,
+            )?;
+            invoke_signed(
+                &ilocked,
+                &[
+                    mint_lp_token_info.clone(),
+                    locked_lp_token_info.clone(),
+                    authority_info.clone(),
+                    token_info.clone(),
+                ],
+                &[authority_seeds],
+            )?;
+        }
 
-        let _ = withdraw.serialize(&mut &mut withdraw_info.data.borrow_mut()[..]);
+        if !pool.compound_fees {
+            Self::update_staked_lp(withdraw_info, new_lp, true)?;
+            pool.staked_lp = pool
+                .staked_lp
+                .checked_add(new_lp)
+                .ok_or(PoolError::MathOverflow)?;
+        }
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Self::record_observation(observations_info, pool_info, &pool)?;
 
         Ok(())
     }
 
-    pub fn slippage_tolerance_check(
-        pool_x_token: Account,
-        pool_y_token: Account,
-        x_amount: u64,
-        y_amount: u64,
-    ) -> bool {
-        let start_ratio: f64 =
-            (pool_x_token.amount - x_amount) as f64 / (pool_y_token.amount - y_amount) as f64;
-        let new_ratio: f64 = pool_x_token.amount as f64 / pool_y_token.amount as f64;
-        let slippage = 1.0 - new_ratio / start_ratio;
-        if slippage.abs() > SLIPPAGE_TOLERANCE as f64 / 100.0 {
-            false
-        } else {
-            true
-        }
-    }
-
-    pub fn swap_tokens(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-        msg!("Swap tokens");
+    fn provide_single_sided(
+        accounts: &[AccountInfo],
+        token: Pubkey,
+        amount: u64,
+        min_lp_out: u64,
+    ) -> ProgramResult {
+        msg!("Providing single sided liquidity");
 
         let acc_iter = &mut accounts.iter();
-        let user_info = next_account_info(acc_iter)?;
-        let user_from_token_info = next_account_info(acc_iter)?;
-        let user_to_token_info = next_account_info(acc_iter)?;
-        let pool_from_token_info = next_account_info(acc_iter)?;
-        let pool_to_token_info = next_account_info(acc_iter)?;
-        let commision_info = next_account_info(acc_iter)?;
-        let admin_info = next_account_info(acc_iter)?;
-        let token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
 
-        if !user_info.is_signer {
-            return Err(PoolError::SignedRequired.into());
+        let pool = Self::load_pool(pool_info)?;
+        Self::validate_liquidity_accounts(accounts, &pool)?;
+        validation::check_writable(pool_info)?;
+
+        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
+        if amount == 0 || total_lp == 0 {
+            return Err(PoolError::ZeroProvide.into());
         }
 
-        let pool_from_token = Account::unpack_from_slice(&pool_from_token_info.data.borrow())?;
-        let pool_to_token = Account::unpack_from_slice(&pool_to_token_info.data.borrow())?;
+        let mut swap = SwapAccounts::from_liquidity_accounts(accounts, pool, &token)?;
+        let x_to_y = swap.x_to_y;
+        let mut snapshot = swap.snapshot()?;
 
-        if amount >= pool_to_token.amount {
-            return Err(PoolError::OverBuy.into());
-        }
+        let amount_in = snapshot
+            .single_sided_swap_amount(amount, x_to_y)
+            .map_err(PoolError::from)?;
+        let result = snapshot
+            .swap_exact_in(amount_in, x_to_y)
+            .map_err(PoolError::from)?;
 
-        let swap_price = Self::swap_price_define(amount, pool_from_token, pool_to_token);
-        let commision_amount: u64 = swap_price * COMMISION_PERCENT / 1000;
-        let user_from_token = Account::unpack_from_slice(&user_from_token_info.data.borrow())?;
+        Self::execute_swap(&mut swap, &result)?;
 
-        if swap_price > user_from_token.amount {
-            return Err(PoolError::TooMuchBuy.into());
-        }
+        let amount_left = amount
+            .checked_sub(amount_in)
+            .ok_or(PoolError::MathOverflow)?;
+        let [max_x, max_y] = if x_to_y {
+            [amount_left, result.amount_out]
+        } else {
+            [result.amount_out, amount_left]
+        };
+        Self::provide_liquidity(accounts, max_x, max_y, min_lp_out)
+    }
 
-        let buy = spl_token::instruction::transfer(
-            token_info.key,
-            pool_to_token_info.key,
-            user_to_token_info.key,
-            admin_info.key,
-            &[admin_info.key],
-            amount,
+    fn withdraw_single_sided(
+        accounts: &[AccountInfo],
+        lp_amount: u64,
+        token: Pubkey,
+        min_out: u64,
+    ) -> ProgramResult {
+        msg!("Withdraw single sided liquidity");
+
+        let acc_iter = &mut accounts.iter();
+        let _ = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let withdraw_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let xy_lp_user_info = next_account_info(acc_iter)?;
+
+        let pool = Self::load_pool(pool_info)?;
+        Self::validate_liquidity_accounts(accounts, &pool)?;
+        validation::check_writable(pool_info)?;
+
+        let xy_lp_user = Self::withdrawable_lp(&pool, pool_info, withdraw_info, xy_lp_user_info)?;
+
+        if lp_amount > xy_lp_user {
+            return Err(PoolError::OverWithdraw.into());
+        }
+
+        // the pool swaps from the other token into `token`
+        let other = if token == pool.mint_x {
+            pool.mint_y
+        } else if token == pool.mint_y {
+            pool.mint_x
+        } else {
+            return Err(PoolError::InvalidMint.into());
+        };
+        let mut swap = SwapAccounts::from_liquidity_accounts(accounts, pool, &other)?;
+        let x_to_y = swap.x_to_y;
+
+        // the withdrawn share of the other token is swapped on what is left
+        let mut snapshot = swap.snapshot()?;
+        let [x_amount, y_amount] = snapshot.withdraw(lp_amount).map_err(PoolError::from)?;
+        let (amount_in, amount_direct) = if x_to_y {
+            (x_amount, y_amount)
+        } else {
+            (y_amount, x_amount)
+        };
+        let result = snapshot
+            .swap_exact_in(amount_in, x_to_y)
+            .map_err(PoolError::from)?;
+
+        let total_out = amount_direct
+            .checked_add(result.amount_out)
+            .ok_or(PoolError::MathOverflow)?;
+        if total_out < min_out {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+
+        Self::withdraw_liquidity(accounts, lp_amount, 0, 0)?;
+        // the withdraw has stored the pool with updated price accumulators
+        swap.pool = Self::load_pool(pool_info)?;
+        if amount_in > 0 {
+            Self::execute_swap(&mut swap, &result)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn swap_tokens(
+        accounts: &[AccountInfo],
+        amount: u64,
+        maximum_amount_in: u64,
+    ) -> ProgramResult {
+        msg!("Swap tokens");
+
+        let mut swap = SwapAccounts::unpack(accounts)?;
+        let result = Self::swap_define(&mut swap, amount, false)?;
+
+        if result.amount_in().map_err(PoolError::from)? > maximum_amount_in {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+
+        Self::execute_swap(&mut swap, &result)
+    }
+
+    pub fn swap_exact_in(
+        accounts: &[AccountInfo],
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> ProgramResult {
+        msg!("Swap exact tokens in");
+
+        let mut swap = SwapAccounts::unpack(accounts)?;
+        let result = Self::swap_define(&mut swap, amount_in, true)?;
+
+        if result.amount_out < minimum_amount_out {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+
+        Self::execute_swap(&mut swap, &result)
+    }
+
+    /// Swaps `amount_in` along `hops` pools, each taking the whole output of
+    /// the previous one. Only the final output is checked against `min_out`.
+    fn route_swap(
+        accounts: &[AccountInfo],
+        amount_in: u64,
+        min_out: u64,
+        hops: u8,
+    ) -> ProgramResult {
+        msg!("Route swap");
+
+        if hops == 0 || accounts.len() != hops as usize * SWAP_ACCOUNTS_LEN {
+            return Err(PoolError::InvalidRoute.into());
+        }
+
+        let mut amount = amount_in;
+        let mut previous_to_token: Option<Pubkey> = None;
+        for hop_accounts in accounts.chunks(SWAP_ACCOUNTS_LEN) {
+            let mut swap = SwapAccounts::unpack(hop_accounts)?;
+            if let Some(to_token) = previous_to_token {
+                if *swap.user_from_token_info.key != to_token {
+                    return Err(PoolError::InvalidRoute.into());
+                }
+            }
+
+            let result = Self::swap_define(&mut swap, amount, true)?;
+            Self::execute_swap(&mut swap, &result)?;
+
+            amount = result.amount_out;
+            previous_to_token = Some(*swap.user_to_token_info.key);
+        }
+
+        if amount < min_out {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+
+        Ok(())
+    }
+
+    /// Prices a swap of `amount`, paid in if `exact_in` or bought otherwise.
+    /// Concentrated liquidity pools move their price and store the crossed
+    /// ticks.
+    fn swap_define(
+        swap: &mut SwapAccounts,
+        amount: u64,
+        exact_in: bool,
+    ) -> Result<SwapResult, ProgramError> {
+        let mut snapshot = swap.snapshot()?;
+        let ticks_info = if swap.pool.curve.is_concentrated() {
+            let ticks_info = swap.ticks_info.ok_or(ProgramError::NotEnoughAccountKeys)?;
+            validation::check_writable(ticks_info)?;
+            snapshot.ticks = Self::load_ticks(ticks_info, swap.pool_info)?;
+            swap.pool
+                .update_cumulatives(0, 0, 0, Clock::get()?.unix_timestamp);
+            Some(ticks_info)
+        } else {
+            None
+        };
+
+        let result = if exact_in {
+            snapshot
+                .swap_exact_in(amount, swap.x_to_y)
+                .map_err(PoolError::from)?
+        } else {
+            snapshot
+                .swap_exact_out(amount, swap.x_to_y)
+                .map_err(PoolError::from)?
+        };
+
+        if let Some(ticks_info) = ticks_info {
+            swap.pool.store_price(&snapshot);
+            if !ticks_info.data_is_empty() {
+                snapshot
+                    .ticks
+                    .serialize(&mut &mut ticks_info.data.borrow_mut()[..])?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Moves the tokens of a priced swap and records its commision split.
+    fn execute_swap(swap: &mut SwapAccounts, result: &SwapResult) -> ProgramResult {
+        let user_from_token = Account::unpack_from_slice(&swap.user_from_token_info.data.borrow())?;
+
+        if result.amount_in().map_err(PoolError::from)? > user_from_token.amount {
+            return Err(PoolError::TooMuchBuy.into());
+        }
+
+        let pool_from_token = Account::unpack_from_slice(&swap.pool_from_token_info.data.borrow())?;
+        let pool_to_token = Account::unpack_from_slice(&swap.pool_to_token_info.data.borrow())?;
+        let (reserve_x, reserve_y) = if swap.x_to_y {
+            (pool_from_token.amount, pool_to_token.amount)
+        } else {
+            (pool_to_token.amount, pool_from_token.amount)
+        };
+        let total_lp = Mint::unpack_from_slice(&swap.mint_lp_token_info.data.borrow())?.supply;
+        swap.pool
+            .update_cumulatives(reserve_x, reserve_y, total_lp, Clock::get()?.unix_timestamp);
+
+        let authority_seeds: &[&[_]] = &[
+            &swap.pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[swap.pool.authority_bump_seed],
+        ];
+
+        let buy = spl_token::instruction::transfer(
+            swap.token_info.key,
+            swap.pool_to_token_info.key,
+            swap.user_to_token_info.key,
+            swap.authority_info.key,
+            &[swap.authority_info.key],
+            result.amount_out,
         )?;
         let pay = spl_token::instruction::transfer(
-            token_info.key,
-            user_from_token_info.key,
-            pool_from_token_info.key,
-            user_info.key,
-            &[user_info.key],
-            swap_price,
+            swap.token_info.key,
+            swap.user_from_token_info.key,
+            swap.pool_from_token_info.key,
+            swap.user_info.key,
+            &[swap.user_info.key],
+            result.pool_amount().map_err(PoolError::from)?,
         )?;
         let comm = spl_token::instruction::transfer(
-            token_info.key,
-            user_from_token_info.key,
-            commision_info.key,
-            user_info.key,
-            &[user_info.key],
-            commision_amount,
+            swap.token_info.key,
+            swap.user_from_token_info.key,
+            swap.commision_info.key,
+            swap.user_info.key,
+            &[swap.user_info.key],
+            result.split.commision_account,
         )?;
-        invoke(
+        invoke_signed(
             &buy,
             &[
-                pool_to_token_info.clone(),
-                user_to_token_info.clone(),
-                admin_info.clone(),
-                token_info.clone(),
+                swap.pool_to_token_info.clone(),
+                swap.user_to_token_info.clone(),
+                swap.authority_info.clone(),
+                swap.token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
         invoke(
             &pay,
             &[
-                user_from_token_info.clone(),
-                pool_from_token_info.clone(),
-                user_info.clone(),
-                token_info.clone(),
+                swap.user_from_token_info.clone(),
+                swap.pool_from_token_info.clone(),
+                swap.user_info.clone(),
+                swap.token_info.clone(),
             ],
         )?;
         invoke(
             &comm,
             &[
-                user_from_token_info.clone(),
+                swap.user_from_token_info.clone(),
+                swap.commision_info.clone(),
+                swap.user_info.clone(),
+                swap.token_info.clone(),
+            ],
+        )?;
+
+        swap.pool
+            .accrue_commision(swap.x_to_y, &result.split)
+            .map_err(PoolError::from)?;
+        swap.pool
+            .serialize(&mut &mut swap.pool_info.data.borrow_mut()[..])?;
+        Self::record_observation(swap.observations_info, swap.pool_info, &swap.pool)?;
+
+        Ok(())
+    }
+
+    fn flash_loan(
+        accounts: &[AccountInfo],
+        token: Pubkey,
+        amount: u64,
+        callback_data: Vec<u8>,
+    ) -> ProgramResult {
+        msg!("Flash loan");
+
+        let acc_iter = &mut accounts.iter();
+        let pool_info = next_account_info(acc_iter)?;
+        let pool_token_info = next_account_info(acc_iter)?;
+        let pool_other_token_info = next_account_info(acc_iter)?;
+        let commision_info = next_account_info(acc_iter)?;
+        let borrower_token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let borrower_program_info = next_account_info(acc_iter)?;
+        let callback_infos = acc_iter.as_slice();
+
+        let mut pool = Self::load_pool(pool_info)?;
+        for account_info in [
+            pool_info,
+            pool_token_info,
+            commision_info,
+            borrower_token_info,
+        ] {
+            validation::check_writable(account_info)?;
+        }
+        let is_x = if token == pool.mint_x {
+            validation::check_vault(pool_token_info, &pool.pool_x_token)?;
+            validation::check_vault(pool_other_token_info, &pool.pool_y_token)?;
+            validation::check_fee_account(commision_info, &pool.commision_x_token)?;
+            true
+        } else if token == pool.mint_y {
+            validation::check_vault(pool_token_info, &pool.pool_y_token)?;
+            validation::check_vault(pool_other_token_info, &pool.pool_x_token)?;
+            validation::check_fee_account(commision_info, &pool.commision_y_token)?;
+            false
+        } else {
+            return Err(PoolError::InvalidMint.into());
+        };
+        validation::check_token_account(borrower_token_info, &token)?;
+        validation::check_lp_mint(mint_lp_token_info, &pool)?;
+        validation::check_authority(authority_info, pool_info.key)?;
+        validation::check_token_program(token_info)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
+
+        let vault_before = Account::unpack_from_slice(&pool_token_info.data.borrow())?.amount;
+        if amount >= vault_before {
+            return Err(PoolError::OverBuy.into());
+        }
+        let commision_amount =
+            pool_math::commision_define(amount, pool.fee_numerator, pool.fee_denominator)
+                .map_err(PoolError::from)?;
+
+        // compounding pools grow their reserves by the commision
+        let other_vault = Account::unpack_from_slice(&pool_other_token_info.data.borrow())?.amount;
+        let (reserve_x, reserve_y) = if is_x {
+            (vault_before, other_vault)
+        } else {
+            (other_vault, vault_before)
+        };
+        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
+        pool.update_cumulatives(reserve_x, reserve_y, total_lp, Clock::get()?.unix_timestamp);
+
+        let lend = spl_token::instruction::transfer(
+            token_info.key,
+            pool_token_info.key,
+            borrower_token_info.key,
+            authority_info.key,
+            &[authority_info.key],
+            amount,
+        )?;
+        invoke_signed(
+            &lend,
+            &[
+                pool_token_info.clone(),
+                borrower_token_info.clone(),
+                authority_info.clone(),
+                token_info.clone(),
+            ],
+            &[authority_seeds],
+        )?;
+
+        let callback = Instruction {
+            program_id: *borrower_program_info.key,
+            accounts: callback_infos
+                .iter()
+                .map(|info| AccountMeta {
+                    pubkey: *info.key,
+                    is_signer: info.is_signer,
+                    is_writable: info.is_writable,
+                })
+                .collect(),
+            data: callback_data,
+        };
+        let mut callback_account_infos = callback_infos.to_vec();
+        callback_account_infos.push(borrower_program_info.clone());
+        invoke(&callback, &callback_account_infos)?;
+
+        let vault_after = Account::unpack_from_slice(&pool_token_info.data.borrow())?.amount;
+        let repaid = vault_before
+            .checked_add(commision_amount)
+            .ok_or(PoolError::MathOverflow)?;
+        if vault_after < repaid {
+            return Err(PoolError::FlashLoanNotRepaid.into());
+        }
+
+        // Commision is moved out of the vault as if paid on a swap,
+        // compounding pools keep the liquidity provider share in the reserves.
+        let split = pool
+            .snapshot(0, 0, total_lp)
+            .commision_split(commision_amount)
+            .map_err(PoolError::from)?;
+        let comm = spl_token::instruction::transfer(
+            token_info.key,
+            pool_token_info.key,
+            commision_info.key,
+            authority_info.key,
+            &[authority_info.key],
+            split.commision_account,
+        )?;
+        invoke_signed(
+            &comm,
+            &[
+                pool_token_info.clone(),
                 commision_info.clone(),
-                user_info.clone(),
+                authority_info.clone(),
                 token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
 
+        pool.accrue_commision(is_x, &split)
+            .map_err(PoolError::from)?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Self::record_observation(observations_info, pool_info, &pool)?;
+
         Ok(())
     }
 
-    pub fn swap_price_define(amount: u64, pool_from_token: Account, pool_to_token: Account) -> u64 {
-        ((amount as f64 / (pool_to_token.amount - amount) as f64) * pool_from_token.amount as f64)
-            as u64
+    pub fn collect_protocol_fees(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("Collect protocol fees");
+
+        let acc_iter = &mut accounts.iter();
+        let admin_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let treasury_x_token_info = next_account_info(acc_iter)?;
+        let treasury_y_token_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::load_pool(pool_info)?;
+        validation::check_admin(admin_info, &pool)?;
+        for account_info in [
+            pool_info,
+            commision_x_token_info,
+            commision_y_token_info,
+            treasury_x_token_info,
+            treasury_y_token_info,
+        ] {
+            validation::check_writable(account_info)?;
+        }
+        validation::check_fee_account(commision_x_token_info, &pool.commision_x_token)?;
+        validation::check_fee_account(commision_y_token_info, &pool.commision_y_token)?;
+        validation::check_fee_account(treasury_x_token_info, &pool.treasury_x_token)?;
+        validation::check_fee_account(treasury_y_token_info, &pool.treasury_y_token)?;
+        validation::check_authority(authority_info, pool_info.key)?;
+        validation::check_token_program(token_info)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
+
+        let ix = spl_token::instruction::transfer(
+            token_info.key,
+            commision_x_token_info.key,
+            treasury_x_token_info.key,
+            authority_info.key,
+            &[authority_info.key],
+            pool.protocol_fee_x,
+        )?;
+        invoke_signed(
+            &ix,
+            &[
+                commision_x_token_info.clone(),
+                treasury_x_token_info.clone(),
+                authority_info.clone(),
+                token_info.clone(),
+            ],
+            &[authority_seeds],
+        )?;
+
+        let iy = spl_token::instruction::transfer(
+            token_info.key,
+            commision_y_token_info.key,
+            treasury_y_token_info.key,
+            authority_info.key,
+            &[authority_info.key],
+            pool.protocol_fee_y,
+        )?;
+        invoke_signed(
+            &iy,
+            &[
+                commision_y_token_info.clone(),
+                treasury_y_token_info.clone(),
+                authority_info.clone(),
+                token_info.clone(),
+            ],
+            &[authority_seeds],
+        )?;
+
+        pool.protocol_fee_x = 0;
+        pool.protocol_fee_y = 0;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+
+        Ok(())
     }
 
-    pub fn withdraw_liquidity(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    pub fn withdraw_liquidity(
+        accounts: &[AccountInfo],
+        amount: u64,
+        min_x_out: u64,
+        min_y_out: u64,
+    ) -> ProgramResult {
         msg!("Withdraw liquidity");
 
         let acc_iter = &mut accounts.iter();
         let user_info = next_account_info(acc_iter)?;
-        let _ = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let withdraw_info = next_account_info(acc_iter)?;
         let x_user_token_info = next_account_info(acc_iter)?;
         let y_user_token_info = next_account_info(acc_iter)?;
         let xy_lp_user_info = next_account_info(acc_iter)?;
@@ -290,17 +1455,26 @@ impl Processor {
         let mint_lp_token_info = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
-        let _ = next_account_info(acc_iter)?;
-        let admin_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
         let token_info = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let staked_lp_token_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::load_pool(pool_info)?;
+        Self::validate_liquidity_accounts(accounts, &pool)?;
+        validation::check_writable(pool_info)?;
+        validation::check_writable(staked_lp_token_info)?;
+        validation::check_staked_lp(staked_lp_token_info, &pool)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
 
-        if !user_info.is_signer {
-            return Err(PoolError::SignedRequired.into());
-        }
-
-        let xy_lp_user = Account::unpack_from_slice(&xy_lp_user_info.data.borrow())?.amount;
+        let xy_lp_user = Self::withdrawable_lp(&pool, pool_info, withdraw_info, xy_lp_user_info)?;
 
         if amount > xy_lp_user {
             return Err(PoolError::OverWithdraw.into());
@@ -310,110 +1484,144 @@ impl Processor {
         let token_y_in_pool = Account::unpack_from_slice(&pool_y_token_info.data.borrow())?.amount;
         let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
         let [x_amount, y_amount] =
-            Self::liquidity_profit(amount, total_lp, token_x_in_pool, token_y_in_pool);
+            pool_math::liquidity_profit(amount, total_lp, token_x_in_pool, token_y_in_pool)
+                .map_err(PoolError::from)?;
+        pool.update_cumulatives(
+            token_x_in_pool,
+            token_y_in_pool,
+            total_lp,
+            Clock::get()?.unix_timestamp,
+        );
 
-        Self::withdraw_fee(accounts)?;
+        if x_amount < min_x_out || y_amount < min_y_out {
+            return Err(PoolError::SlippageExceeded.into());
+        }
 
-        let user_withdraw = spl_token::instruction::burn(
-            token_info.key,
-            xy_lp_user_info.key,
-            mint_lp_token_info.key,
-            user_info.key,
-            &[user_info.key],
-            amount,
-        )?;
+        if pool.compound_fees {
+            let user_withdraw = spl_token::instruction::burn(
+                token_info.key,
+                xy_lp_user_info.key,
+                mint_lp_token_info.key,
+                user_info.key,
+                &[user_info.key],
+                amount,
+            )?;
 
-        invoke(
-            &user_withdraw,
-            &[
-                mint_lp_token_info.clone(),
-                xy_lp_user_info.clone(),
-                user_info.clone(),
-                token_info.clone(),
-            ],
-        )?;
+            invoke(
+                &user_withdraw,
+                &[
+                    mint_lp_token_info.clone(),
+                    xy_lp_user_info.clone(),
+                    user_info.clone(),
+                    token_info.clone(),
+                ],
+            )?;
+        } else {
+            Self::withdraw_fee(accounts)?;
+
+            let staked_withdraw = spl_token::instruction::burn(
+                token_info.key,
+                staked_lp_token_info.key,
+                mint_lp_token_info.key,
+                authority_info.key,
+                &[authority_info.key],
+                amount,
+            )?;
+
+            invoke_signed(
+                &staked_withdraw,
+                &[
+                    mint_lp_token_info.clone(),
+                    staked_lp_token_info.clone(),
+                    authority_info.clone(),
+                    token_info.clone(),
+                ],
+                &[authority_seeds],
+            )?;
+
+            Self::update_staked_lp(withdraw_info, amount, false)?;
+            pool.staked_lp = pool
+                .staked_lp
+                .checked_sub(amount)
+                .ok_or(PoolError::MathOverflow)?;
+        }
 
         let ix = spl_token::instruction::transfer(
             token_info.key,
             pool_x_token_info.key,
             x_user_token_info.key,
-            admin_info.key,
-            &[admin_info.key],
+            authority_info.key,
+            &[authority_info.key],
             x_amount,
         )?;
-        invoke(
+        invoke_signed(
             &ix,
             &[
                 x_user_token_info.clone(),
                 pool_x_token_info.clone(),
-                admin_info.clone(),
+                authority_info.clone(),
                 token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
 
         let iy = spl_token::instruction::transfer(
             token_info.key,
             pool_y_token_info.key,
             y_user_token_info.key,
-            admin_info.key,
-            &[admin_info.key],
+            authority_info.key,
+            &[authority_info.key],
             y_amount,
         )?;
-        invoke(
+        invoke_signed(
             &iy,
             &[
                 y_user_token_info.clone(),
                 pool_y_token_info.clone(),
-                admin_info.clone(),
+                authority_info.clone(),
                 token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Self::record_observation(observations_info, pool_info, &pool)?;
 
         Ok(())
     }
 
-    pub fn liquidity_profit(
-        amount: u64,
-        total_lp: u64,
-        token_x_in_pool: u64,
-        token_y_in_pool: u64,
-    ) -> [u64; 2] {
-        if total_lp == 0 {
-            return [0, 0];
-        }
-        let ratio = amount as f64 / total_lp as f64;
-        [
-            (token_x_in_pool as f64 * ratio) as u64,
-            (token_y_in_pool as f64 * ratio) as u64,
-        ]
-    }
-
     pub fn withdraw_fee(accounts: &[AccountInfo]) -> ProgramResult {
         msg!("Withdraw commision");
-        
+
         let acc_iter = &mut accounts.iter();
         let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
         let withdraw_info = next_account_info(acc_iter)?;
         let x_user_token_info = next_account_info(acc_iter)?;
         let y_user_token_info = next_account_info(acc_iter)?;
         let xy_lp_user_info = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
         let _ = next_account_info(acc_iter)?;
-        let mint_lp_token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
         let current_comission_x_tokem_info = next_account_info(acc_iter)?;
         let current_comission_y_tokem_info = next_account_info(acc_iter)?;
-        let total_commision_info = next_account_info(acc_iter)?;
-        let admin_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
         let token_info = next_account_info(acc_iter)?;
         let rent_info = next_account_info(acc_iter)?;
         let system_program_info = next_account_info(acc_iter)?;
 
-        if !user_info.is_signer {
-            return Err(PoolError::SignedRequired.into());
+        let pool = Self::load_pool(pool_info)?;
+        if pool.compound_fees {
+            return Err(PoolError::FeesCompounded.into());
         }
+        Self::validate_liquidity_accounts(accounts, &pool)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
 
         let (withdraw_pubkey, bump_seed) =
-            WithdrawedFee::get_withdraw_pubkey_with_bump(user_info.key);
+            WithdrawedFee::get_withdraw_pubkey_with_bump(pool_info.key, xy_lp_user_info.key);
 
         if withdraw_pubkey != *withdraw_info.key {
             return Err(PoolError::WrongWithdraw.into());
@@ -422,137 +1630,1016 @@ impl Processor {
         if withdraw_info.data_is_empty() {
             msg!("creating new withdraw");
             let withdraw = WithdrawedFee {
-                user_x_withdraw: 0,
-                user_y_withdraw: 0,
+                lp_staked: 0,
+                fee_growth_x_checkpoint: pool.fee_growth_x,
+                fee_growth_y_checkpoint: pool.fee_growth_y,
             };
             let space = withdraw.try_to_vec()?.len();
             let rent = &Rent::from_account_info(rent_info)?;
-            let lamports = rent.minimum_balance(space);
             let signer_seeds: &[&[_]] = &[
-                &user_info.key.to_bytes(),
+                &pool_info.key.to_bytes(),
+                &xy_lp_user_info.key.to_bytes(),
                 POOL_SEED.as_bytes(),
                 &[bump_seed],
             ];
-            invoke_signed(
-                &system_instruction::create_account(
-                    user_info.key,
-                    &withdraw_pubkey,
-                    lamports,
-                    space as u64,
-                    &id(),
-                ),
-                &[
-                    user_info.clone(),
-                    withdraw_info.clone(),
-                    system_program_info.clone(),
-                ],
-                &[signer_seeds],
-            )?;
-            let _ = withdraw.serialize(&mut &mut withdraw_info.data.borrow_mut()[..]);
-        }
-
-        let (total_commision_pubkey, bump_seed) = TotalCommision::get_total_pubkey_with_bump();
-
-        if total_commision_pubkey != *total_commision_info.key {
-            return Err(PoolError::WrongWithdraw.into());
-        }
-
-        if total_commision_info.data_is_empty() {
-            msg!("creating total commision");
-            let total = TotalCommision {
-                total_x_commision: 0,
-                total_y_commision: 0,
-            };
-            let space = total.try_to_vec()?.len();
-            let rent = &Rent::from_account_info(rent_info)?;
-            let lamports = rent.minimum_balance(space);
-            let signer_seeds: &[&[_]] = &[&id().to_bytes(), POOL_SEED.as_bytes(), &[bump_seed]];
-            invoke_signed(
-                &system_instruction::create_account(
-                    user_info.key,
-                    &total_commision_pubkey,
-                    lamports,
-                    space as u64,
-                    &id(),
-                ),
-                &[
-                    user_info.clone(),
-                    total_commision_info.clone(),
-                    system_program_info.clone(),
-                ],
-                &[signer_seeds],
+            Self::create_pda(
+                user_info,
+                withdraw_info,
+                system_program_info,
+                rent,
+                space,
+                &id(),
+                signer_seeds,
             )?;
-            let _ = total.serialize(&mut &mut total_commision_info.data.borrow_mut()[..]);
+            withdraw.serialize(&mut &mut withdraw_info.data.borrow_mut()[..])?;
         }
 
-        let mut total_commision =
-            TotalCommision::try_from_slice(&total_commision_info.data.borrow())?;
-
-        let token_x_commision =
-            Account::unpack_from_slice(&current_comission_x_tokem_info.data.borrow())?.amount;
-        let token_y_commision =
-            Account::unpack_from_slice(&current_comission_y_tokem_info.data.borrow())?.amount;
-        let total_lp = Mint::unpack_from_slice(&mint_lp_token_info.data.borrow())?.supply;
-        let user_lp = Account::unpack_from_slice(&xy_lp_user_info.data.borrow())?.amount;
-
-        let [x_amount, y_amount] = Self::liquidity_profit(
-            user_lp,
-            total_lp,
-            token_x_commision + total_commision.total_x_commision,
-            token_y_commision + total_commision.total_y_commision,
-        );
-
         let mut withdraw = WithdrawedFee::try_from_slice(&withdraw_info.data.borrow())?;
 
-        let [x_amount, y_amount] = [
-            x_amount - withdraw.user_x_withdraw,
-            y_amount - withdraw.user_y_withdraw,
-        ];
-
-        withdraw.user_x_withdraw += x_amount;
-        withdraw.user_y_withdraw += y_amount;
-        total_commision.total_x_commision += x_amount;
-        total_commision.total_y_commision += y_amount;
-
-        let _ = withdraw.serialize(&mut &mut withdraw_info.data.borrow_mut()[..]);
-        let _ = total_commision.serialize(&mut &mut total_commision_info.data.borrow_mut()[..]);
+        let x_amount = pool_math::fee_profit(
+            withdraw.lp_staked,
+            pool.fee_growth_x,
+            withdraw.fee_growth_x_checkpoint,
+        )
+        .map_err(PoolError::from)?;
+        let y_amount = pool_math::fee_profit(
+            withdraw.lp_staked,
+            pool.fee_growth_y,
+            withdraw.fee_growth_y_checkpoint,
+        )
+        .map_err(PoolError::from)?;
+
+        withdraw.fee_growth_x_checkpoint = pool.fee_growth_x;
+        withdraw.fee_growth_y_checkpoint = pool.fee_growth_y;
+        withdraw.serialize(&mut &mut withdraw_info.data.borrow_mut()[..])?;
 
         let ix = spl_token::instruction::transfer(
             token_info.key,
             current_comission_x_tokem_info.key,
             x_user_token_info.key,
-            admin_info.key,
-            &[admin_info.key],
+            authority_info.key,
+            &[authority_info.key],
             x_amount,
         )?;
-        invoke(
+        invoke_signed(
             &ix,
             &[
                 x_user_token_info.clone(),
                 current_comission_x_tokem_info.clone(),
-                admin_info.clone(),
+                authority_info.clone(),
                 token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
 
         let iy = spl_token::instruction::transfer(
             token_info.key,
             current_comission_y_tokem_info.key,
             y_user_token_info.key,
-            admin_info.key,
-            &[admin_info.key],
+            authority_info.key,
+            &[authority_info.key],
             y_amount,
         )?;
-        invoke(
+        invoke_signed(
             &iy,
             &[
                 y_user_token_info.clone(),
                 current_comission_y_tokem_info.clone(),
-                admin_info.clone(),
+                authority_info.clone(),
                 token_info.clone(),
             ],
+            &[authority_seeds],
         )?;
 
         Ok(())
     }
+
+    /// Adds `amount` lp tokens to the fee position if `stake`, removes them
+    /// otherwise. Called after `withdraw_fee` has paid out the commision
+    /// earned by the previous amount.
+    fn update_staked_lp(withdraw_info: &AccountInfo, amount: u64, stake: bool) -> ProgramResult {
+        let mut withdraw = WithdrawedFee::try_from_slice(&withdraw_info.data.borrow())?;
+        withdraw.lp_staked = if stake {
+            withdraw
+                .lp_staked
+                .checked_add(amount)
+                .ok_or(PoolError::MathOverflow)?
+        } else {
+            withdraw
+                .lp_staked
+                .checked_sub(amount)
+                .ok_or(PoolError::OverWithdraw)?
+        };
+        withdraw.serialize(&mut &mut withdraw_info.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    /// Lp tokens the user can withdraw: staked by the fee position of the
+    /// user`s lp token account, or held by it on a pool compounding its
+    /// commision.
+    fn withdrawable_lp(
+        pool: &Pool,
+        pool_info: &AccountInfo,
+        withdraw_info: &AccountInfo,
+        xy_lp_user_info: &AccountInfo,
+    ) -> Result<u64, ProgramError> {
+        if pool.compound_fees {
+            return Ok(Account::unpack_from_slice(&xy_lp_user_info.data.borrow())?.amount);
+        }
+        if *withdraw_info.key
+            != WithdrawedFee::get_withdraw_pubkey(pool_info.key, xy_lp_user_info.key)
+        {
+            return Err(PoolError::WrongWithdraw.into());
+        }
+        if withdraw_info.data_is_empty() {
+            return Ok(0);
+        }
+        Ok(WithdrawedFee::try_from_slice(&withdraw_info.data.borrow())?.lp_staked)
+    }
+
+    /// Checks the accounts of `StakeLp` and `UnstakeLp`, paying out the
+    /// commision earned by the fee position so far.
+    fn settle_staking(accounts: &[AccountInfo]) -> Result<Pool, ProgramError> {
+        let acc_iter = &mut accounts.iter();
+        let _ = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        for _ in 0..15 {
+            next_account_info(acc_iter)?;
+        }
+        let staked_lp_token_info = next_account_info(acc_iter)?;
+
+        let pool = Self::load_pool(pool_info)?;
+        if pool.compound_fees {
+            return Err(PoolError::FeesCompounded.into());
+        }
+        Self::validate_liquidity_accounts(accounts, &pool)?;
+        validation::check_writable(pool_info)?;
+        validation::check_writable(staked_lp_token_info)?;
+        validation::check_staked_lp(staked_lp_token_info, &pool)?;
+
+        Self::withdraw_fee(accounts)?;
+        Ok(pool)
+    }
+
+    fn stake_lp(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+        msg!("Stake lp");
+
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let withdraw_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let xy_lp_user_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let staked_lp_token_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::settle_staking(accounts)?;
+
+        let xy_lp_user = Account::unpack_from_slice(&xy_lp_user_info.data.borrow())?.amount;
+        if amount > xy_lp_user {
+            return Err(PoolError::OverProvide.into());
+        }
+
+        Self::transfer(
+            token_info,
+            xy_lp_user_info,
+            staked_lp_token_info,
+            user_info,
+            amount,
+            &[],
+        )?;
+        Self::update_staked_lp(withdraw_info, amount, true)?;
+        pool.staked_lp = pool
+            .staked_lp
+            .checked_add(amount)
+            .ok_or(PoolError::MathOverflow)?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    fn unstake_lp(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+        msg!("Unstake lp");
+
+        let acc_iter = &mut accounts.iter();
+        let _ = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let withdraw_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let xy_lp_user_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let staked_lp_token_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::settle_staking(accounts)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
+
+        Self::update_staked_lp(withdraw_info, amount, false)?;
+        pool.staked_lp = pool
+            .staked_lp
+            .checked_sub(amount)
+            .ok_or(PoolError::MathOverflow)?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Self::transfer(
+            token_info,
+            staked_lp_token_info,
+            xy_lp_user_info,
+            authority_info,
+            amount,
+            &[authority_seeds],
+        )
+    }
+
+    /// Checks the account layout of `ProvideConcentrated` and
+    /// `WithdrawConcentrated` and the tick range against the pool.
+    fn validate_position_accounts(
+        accounts: &[AccountInfo],
+        pool: &Pool,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> ProgramResult {
+        let tick_spacing = match pool.curve {
+            CurveType::Concentrated { tick_spacing, .. } => tick_spacing as i32,
+            _ => return Err(PoolError::WrongCurve.into()),
+        };
+        if tick_lower >= tick_upper
+            || tick_lower < concentrated::MIN_TICK
+            || tick_upper > concentrated::MAX_TICK
+            || tick_lower % tick_spacing != 0
+            || tick_upper % tick_spacing != 0
+        {
+            return Err(PoolError::InvalidTickRange.into());
+        }
+
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let position_info = next_account_info(acc_iter)?;
+        let ticks_info = next_account_info(acc_iter)?;
+        let x_user_token_info = next_account_info(acc_iter)?;
+        let y_user_token_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+
+        validation::check_signer(user_info)?;
+        for account_info in [
+            user_info,
+            pool_info,
+            position_info,
+            ticks_info,
+            x_user_token_info,
+            y_user_token_info,
+            pool_x_token_info,
+            pool_y_token_info,
+            commision_x_token_info,
+            commision_y_token_info,
+        ] {
+            validation::check_writable(account_info)?;
+        }
+
+        validation::check_token_account(x_user_token_info, &pool.mint_x)?;
+        validation::check_token_account(y_user_token_info, &pool.mint_y)?;
+        validation::check_vault(pool_x_token_info, &pool.pool_x_token)?;
+        validation::check_vault(pool_y_token_info, &pool.pool_y_token)?;
+        validation::check_fee_account(commision_x_token_info, &pool.commision_x_token)?;
+        validation::check_fee_account(commision_y_token_info, &pool.commision_y_token)?;
+        validation::check_authority(authority_info, pool_info.key)?;
+        validation::check_token_program(token_info)?;
+
+        let position_pubkey =
+            Position::get_position_pubkey(pool_info.key, user_info.key, tick_lower, tick_upper);
+        if position_pubkey != *position_info.key {
+            return Err(PoolError::InvalidPosition.into());
+        }
+
+        Ok(())
+    }
+
+    fn provide_concentrated(
+        accounts: &[AccountInfo],
+        tick_lower: i32,
+        tick_upper: i32,
+        max_x: u64,
+        max_y: u64,
+        min_liquidity: u128,
+    ) -> ProgramResult {
+        msg!("Providing concentrated liquidity");
+
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let position_info = next_account_info(acc_iter)?;
+        let ticks_info = next_account_info(acc_iter)?;
+        let x_user_token_info = next_account_info(acc_iter)?;
+        let y_user_token_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::load_pool(pool_info)?;
+        Self::validate_position_accounts(accounts, &pool, tick_lower, tick_upper)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
+        pool.update_cumulatives(0, 0, 0, Clock::get()?.unix_timestamp);
+
+        let sqrt_lower =
+            concentrated::sqrt_price_at_tick(tick_lower).ok_or(PoolError::InvalidTickRange)?;
+        let sqrt_upper =
+            concentrated::sqrt_price_at_tick(tick_upper).ok_or(PoolError::InvalidTickRange)?;
+        let liquidity = concentrated::liquidity_for_amounts(
+            pool.sqrt_price,
+            sqrt_lower,
+            sqrt_upper,
+            max_x,
+            max_y,
+        )
+        .ok_or(PoolError::MathOverflow)?;
+        if liquidity == 0 {
+            return Err(PoolError::ZeroLiquidity.into());
+        }
+        if liquidity < min_liquidity {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+        let [x_amount, y_amount] = concentrated::amounts_for_liquidity(
+            pool.sqrt_price,
+            sqrt_lower,
+            sqrt_upper,
+            liquidity,
+            Rounding::Up,
+        )
+        .ok_or(PoolError::MathOverflow)?;
+
+        let x_user_token = Account::unpack_from_slice(&x_user_token_info.data.borrow())?.amount;
+        let y_user_token = Account::unpack_from_slice(&y_user_token_info.data.borrow())?.amount;
+
+        if x_amount > max_x || y_amount > max_y {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+        if x_amount > x_user_token || y_amount > y_user_token {
+            return Err(PoolError::OverProvide.into());
+        }
+
+        let mut position = if position_info.data_is_empty() {
+            Self::create_position(
+                user_info,
+                pool_info,
+                position_info,
+                system_program_info,
+                tick_lower,
+                tick_upper,
+            )?
+        } else {
+            Self::load_position(position_info, user_info)?
+        };
+        let mut ticks = Self::load_ticks(ticks_info, pool_info)?;
+        let [fee_x, fee_y] =
+            Self::update_position(&mut pool, &mut position, &mut ticks, liquidity as i128)?;
+
+        Self::transfer(
+            token_info,
+            x_user_token_info,
+            pool_x_token_info,
+            user_info,
+            x_amount,
+            &[],
+        )?;
+        Self::transfer(
+            token_info,
+            y_user_token_info,
+            pool_y_token_info,
+            user_info,
+            y_amount,
+            &[],
+        )?;
+        Self::transfer(
+            token_info,
+            commision_x_token_info,
+            x_user_token_info,
+            authority_info,
+            fee_x,
+            &[authority_seeds],
+        )?;
+        Self::transfer(
+            token_info,
+            commision_y_token_info,
+            y_user_token_info,
+            authority_info,
+            fee_y,
+            &[authority_seeds],
+        )?;
+
+        position.serialize(&mut &mut position_info.data.borrow_mut()[..])?;
+        Self::store_ticks(
+            ticks_info,
+            pool_info,
+            user_info,
+            system_program_info,
+            &ticks,
+        )?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Self::record_observation(observations_info, pool_info, &pool)?;
+
+        Ok(())
+    }
+
+    fn withdraw_concentrated(
+        accounts: &[AccountInfo],
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        min_x_out: u64,
+        min_y_out: u64,
+    ) -> ProgramResult {
+        msg!("Withdraw concentrated liquidity");
+
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let position_info = next_account_info(acc_iter)?;
+        let ticks_info = next_account_info(acc_iter)?;
+        let x_user_token_info = next_account_info(acc_iter)?;
+        let y_user_token_info = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let system_program_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+
+        let mut pool = Self::load_pool(pool_info)?;
+        Self::validate_position_accounts(accounts, &pool, tick_lower, tick_upper)?;
+        let authority_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            POOL_SEED.as_bytes(),
+            &[pool.authority_bump_seed],
+        ];
+        pool.update_cumulatives(0, 0, 0, Clock::get()?.unix_timestamp);
+
+        if position_info.data_is_empty() {
+            return Err(PoolError::InvalidPosition.into());
+        }
+        let mut position = Self::load_position(position_info, user_info)?;
+        if liquidity > position.liquidity {
+            return Err(PoolError::OverWithdraw.into());
+        }
+
+        let sqrt_lower =
+            concentrated::sqrt_price_at_tick(tick_lower).ok_or(PoolError::InvalidTickRange)?;
+        let sqrt_upper =
+            concentrated::sqrt_price_at_tick(tick_upper).ok_or(PoolError::InvalidTickRange)?;
+        let [x_amount, y_amount] = concentrated::amounts_for_liquidity(
+            pool.sqrt_price,
+            sqrt_lower,
+            sqrt_upper,
+            liquidity,
+            Rounding::Down,
+        )
+        .ok_or(PoolError::MathOverflow)?;
+
+        if x_amount < min_x_out || y_amount < min_y_out {
+            return Err(PoolError::SlippageExceeded.into());
+        }
+
+        let liquidity_delta = -i128::try_from(liquidity).map_err(|_| PoolError::MathOverflow)?;
+        let mut ticks = Self::load_ticks(ticks_info, pool_info)?;
+        let [fee_x, fee_y] =
+            Self::update_position(&mut pool, &mut position, &mut ticks, liquidity_delta)?;
+
+        Self::transfer(
+            token_info,
+            pool_x_token_info,
+            x_user_token_info,
+            authority_info,
+            x_amount,
+            &[authority_seeds],
+        )?;
+        Self::transfer(
+            token_info,
+            pool_y_token_info,
+            y_user_token_info,
+            authority_info,
+            y_amount,
+            &[authority_seeds],
+        )?;
+        Self::transfer(
+            token_info,
+            commision_x_token_info,
+            x_user_token_info,
+            authority_info,
+            fee_x,
+            &[authority_seeds],
+        )?;
+        Self::transfer(
+            token_info,
+            commision_y_token_info,
+            y_user_token_info,
+            authority_info,
+            fee_y,
+            &[authority_seeds],
+        )?;
+
+        position.serialize(&mut &mut position_info.data.borrow_mut()[..])?;
+        Self::store_ticks(
+            ticks_info,
+            pool_info,
+            user_info,
+            system_program_info,
+            &ticks,
+        )?;
+        pool.serialize(&mut &mut pool_info.data.borrow_mut()[..])?;
+        Self::record_observation(observations_info, pool_info, &pool)?;
+
+        Ok(())
+    }
+
+    /// Adds `liquidity_delta` to a position, its ticks and the pool liquidity
+    /// if the position is in range. Returns the commision the position has
+    /// earned since its last checkpoint, `[fee_x, fee_y]`. Fails on a
+    /// position left below `MIN_POSITION_LIQUIDITY` or more than `MAX_TICKS`
+    /// initialized ticks.
+    fn update_position(
+        pool: &mut Pool,
+        position: &mut Position,
+        ticks: &mut Ticks,
+        liquidity_delta: i128,
+    ) -> Result<[u64; 2], ProgramError> {
+        for (tick, upper) in [(position.tick_lower, false), (position.tick_upper, true)] {
+            ticks
+                .update(
+                    tick,
+                    liquidity_delta,
+                    upper,
+                    pool.tick_current,
+                    pool.fee_growth_x,
+                    pool.fee_growth_y,
+                )
+                .ok_or(PoolError::MathOverflow)?;
+        }
+        let lower = ticks
+            .get(position.tick_lower)
+            .ok_or(PoolError::InvalidTicks)?;
+        let upper = ticks
+            .get(position.tick_upper)
+            .ok_or(PoolError::InvalidTicks)?;
+        let inside_x = concentrated::fee_growth_inside(
+            pool.tick_current,
+            pool.fee_growth_x,
+            lower.index,
+            lower.fee_growth_outside_x,
+            upper.index,
+            upper.fee_growth_outside_x,
+        );
+        let inside_y = concentrated::fee_growth_inside(
+            pool.tick_current,
+            pool.fee_growth_y,
+            lower.index,
+            lower.fee_growth_outside_y,
+            upper.index,
+            upper.fee_growth_outside_y,
+        );
+        let fee_x = pool_math::position_fee_define(
+            position.liquidity,
+            inside_x,
+            position.fee_growth_inside_x_checkpoint,
+        )
+        .map_err(PoolError::from)?;
+        let fee_y = pool_math::position_fee_define(
+            position.liquidity,
+            inside_y,
+            position.fee_growth_inside_y_checkpoint,
+        )
+        .map_err(PoolError::from)?;
+
+        position.liquidity = concentrated::add_liquidity_delta(position.liquidity, liquidity_delta)
+            .ok_or(PoolError::OverWithdraw)?;
+        if position.liquidity != 0 && position.liquidity < concentrated::MIN_POSITION_LIQUIDITY {
+            return Err(PoolError::PositionTooSmall.into());
+        }
+        position.fee_growth_inside_x_checkpoint = inside_x;
+        position.fee_growth_inside_y_checkpoint = inside_y;
+        if (position.tick_lower..position.tick_upper).contains(&pool.tick_current) {
+            pool.liquidity = concentrated::add_liquidity_delta(pool.liquidity, liquidity_delta)
+                .ok_or(PoolError::MathOverflow)?;
+        }
+        ticks.remove_unused();
+        if ticks.ticks.len() > concentrated::MAX_TICKS {
+            return Err(PoolError::TooManyTicks.into());
+        }
+
+        Ok([fee_x, fee_y])
+    }
+
+    fn create_position<'a>(
+        user_info: &AccountInfo<'a>,
+        pool_info: &AccountInfo<'a>,
+        position_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<Position, ProgramError> {
+        msg!("creating new position");
+        let position = Position {
+            owner: *user_info.key,
+            tick_lower,
+            tick_upper,
+            liquidity: 0,
+            fee_growth_inside_x_checkpoint: 0,
+            fee_growth_inside_y_checkpoint: 0,
+        };
+        let space = position.try_to_vec()?.len();
+        let (_, bump_seed) = Position::get_position_pubkey_with_bump(
+            pool_info.key,
+            user_info.key,
+            tick_lower,
+            tick_upper,
+        );
+        let signer_seeds: &[&[_]] = &[
+            &pool_info.key.to_bytes(),
+            &user_info.key.to_bytes(),
+            &tick_lower.to_le_bytes(),
+            &tick_upper.to_le_bytes(),
+            POSITION_SEED.as_bytes(),
+            &[bump_seed],
+        ];
+        Self::create_pda(
+            user_info,
+            position_info,
+            system_program_info,
+            &Rent::get()?,
+            space,
+            &id(),
+            signer_seeds,
+        )?;
+        Ok(position)
+    }
+
+    fn load_position(
+        position_info: &AccountInfo,
+        user_info: &AccountInfo,
+    ) -> Result<Position, ProgramError> {
+        if *position_info.owner != id() {
+            return Err(PoolError::InvalidPosition.into());
+        }
+        let position = Position::try_from_slice(&position_info.data.borrow())?;
+        if position.owner != *user_info.key {
+            return Err(PoolError::InvalidPosition.into());
+        }
+        Ok(position)
+    }
+
+    /// Initialized ticks of a concentrated liquidity pool, none before its
+    /// ticks account is created.
+    fn load_ticks(
+        ticks_info: &AccountInfo,
+        pool_info: &AccountInfo,
+    ) -> Result<Ticks, ProgramError> {
+        if *ticks_info.key != get_ticks_pubkey(pool_info.key) {
+            return Err(PoolError::InvalidTicks.into());
+        }
+        if ticks_info.data_is_empty() {
+            return Ok(Ticks::default());
+        }
+        if *ticks_info.owner != id() {
+            return Err(PoolError::InvalidTicks.into());
+        }
+        Ok(Ticks::try_from_slice(&ticks_info.data.borrow())?)
+    }
+
+    /// Writes the ticks of a concentrated liquidity pool, creating or
+    /// resizing their account. The payer funds the rent of a larger account.
+    fn store_ticks<'a>(
+        ticks_info: &AccountInfo<'a>,
+        pool_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        ticks: &Ticks,
+    ) -> ProgramResult {
+        let space = ticks.try_to_vec()?.len();
+        let rent = Rent::get()?;
+        if ticks_info.data_is_empty() {
+            let (_, bump_seed) = get_ticks_pubkey_with_bump(pool_info.key);
+            let signer_seeds: &[&[_]] = &[
+                &pool_info.key.to_bytes(),
+                TICKS_SEED.as_bytes(),
+                &[bump_seed],
+            ];
+            Self::create_pda(
+                payer_info,
+                ticks_info,
+                system_program_info,
+                &rent,
+                space,
+                &id(),
+                signer_seeds,
+            )?;
+        } else if space != ticks_info.data_len() {
+            let lamports = rent
+                .minimum_balance(space)
+                .saturating_sub(ticks_info.lamports());
+            if lamports > 0 {
+                invoke(
+                    &system_instruction::transfer(payer_info.key, ticks_info.key, lamports),
+                    &[
+                        payer_info.clone(),
+                        ticks_info.clone(),
+                        system_program_info.clone(),
+                    ],
+                )?;
+            }
+            ticks_info.realloc(space, false)?;
+        }
+        ticks.serialize(&mut &mut ticks_info.data.borrow_mut()[..])?;
+        Ok(())
+    }
+
+    /// Transfers `amount` signed by `authority_info`, with `signer_seeds`
+    /// when it is the pool authority.
+    fn transfer<'a>(
+        token_info: &AccountInfo<'a>,
+        source_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        amount: u64,
+        signer_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let ix = spl_token::instruction::transfer(
+            token_info.key,
+            source_info.key,
+            destination_info.key,
+            authority_info.key,
+            &[authority_info.key],
+            amount,
+        )?;
+        invoke_signed(
+            &ix,
+            &[
+                source_info.clone(),
+                destination_info.clone(),
+                authority_info.clone(),
+                token_info.clone(),
+            ],
+            signer_seeds,
+        )
+    }
+
+    /// Creates the program derived account `account_info` with `space` bytes
+    /// owned by `owner`, paid by `payer_info`. Lamports already sent to the
+    /// address count towards its rent, so funding it first blocks nothing.
+    fn create_pda<'a>(
+        payer_info: &AccountInfo<'a>,
+        account_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+        space: usize,
+        owner: &Pubkey,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let lamports = rent.minimum_balance(space);
+        if account_info.lamports() == 0 {
+            return invoke_signed(
+                &system_instruction::create_account(
+                    payer_info.key,
+                    account_info.key,
+                    lamports,
+                    space as u64,
+                    owner,
+                ),
+                &[
+                    payer_info.clone(),
+                    account_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[signer_seeds],
+            );
+        }
+
+        let missing = lamports.saturating_sub(account_info.lamports());
+        if missing > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, missing),
+                &[
+                    payer_info.clone(),
+                    account_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        let accounts = &[account_info.clone(), system_program_info.clone()];
+        invoke_signed(
+            &system_instruction::allocate(account_info.key, space as u64),
+            accounts,
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(account_info.key, owner),
+            accounts,
+            &[signer_seeds],
+        )
+    }
+}
+
+/// Number of accounts of `SwapTokens`, `SwapExactIn` and every `RouteSwap` hop.
+const SWAP_ACCOUNTS_LEN: usize = 12;
+
+/// Accounts of `SwapTokens` and `SwapExactIn`, validated against the pool.
+struct SwapAccounts<'a, 'b> {
+    user_info: &'a AccountInfo<'b>,
+    pool_info: &'a AccountInfo<'b>,
+    user_from_token_info: &'a AccountInfo<'b>,
+    user_to_token_info: &'a AccountInfo<'b>,
+    pool_from_token_info: &'a AccountInfo<'b>,
+    pool_to_token_info: &'a AccountInfo<'b>,
+    commision_info: &'a AccountInfo<'b>,
+    authority_info: &'a AccountInfo<'b>,
+    token_info: &'a AccountInfo<'b>,
+    mint_lp_token_info: &'a AccountInfo<'b>,
+    observations_info: &'a AccountInfo<'b>,
+    ticks_info: Option<&'a AccountInfo<'b>>,
+    pool: Pool,
+    x_to_y: bool,
+}
+
+impl<'a, 'b> SwapAccounts<'a, 'b> {
+    fn unpack(accounts: &'a [AccountInfo<'b>]) -> Result<Self, ProgramError> {
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let user_from_token_info = next_account_info(acc_iter)?;
+        let user_to_token_info = next_account_info(acc_iter)?;
+        let pool_from_token_info = next_account_info(acc_iter)?;
+        let pool_to_token_info = next_account_info(acc_iter)?;
+        let commision_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+        let ticks_info = acc_iter.next();
+
+        validation::check_signer(user_info)?;
+        for account_info in [
+            pool_info,
+            user_from_token_info,
+            user_to_token_info,
+            pool_from_token_info,
+            pool_to_token_info,
+            commision_info,
+        ] {
+            validation::check_writable(account_info)?;
+        }
+
+        let pool = Processor::load_pool(pool_info)?;
+        validation::check_lp_mint(mint_lp_token_info, &pool)?;
+        let x_to_y = *pool_from_token_info.key == pool.pool_x_token;
+        let (mint_from, mint_to, commision_from_token) = if x_to_y {
+            validation::check_vault(pool_to_token_info, &pool.pool_y_token)?;
+            (pool.mint_x, pool.mint_y, pool.commision_x_token)
+        } else {
+            validation::check_vault(pool_from_token_info, &pool.pool_y_token)?;
+            validation::check_vault(pool_to_token_info, &pool.pool_x_token)?;
+            (pool.mint_y, pool.mint_x, pool.commision_y_token)
+        };
+        validation::check_token_account(user_from_token_info, &mint_from)?;
+        validation::check_token_account(user_to_token_info, &mint_to)?;
+        validation::check_fee_account(commision_info, &commision_from_token)?;
+        validation::check_authority(authority_info, pool_info.key)?;
+        validation::check_token_program(token_info)?;
+
+        Ok(SwapAccounts {
+            user_info,
+            pool_info,
+            user_from_token_info,
+            user_to_token_info,
+            pool_from_token_info,
+            pool_to_token_info,
+            commision_info,
+            authority_info,
+            token_info,
+            mint_lp_token_info,
+            observations_info,
+            ticks_info,
+            pool,
+            x_to_y,
+        })
+    }
+
+    /// Snapshot of the pool with the balances of its token accounts.
+    fn snapshot(&self) -> Result<PoolSnapshot, ProgramError> {
+        let reserve_from =
+            Account::unpack_from_slice(&self.pool_from_token_info.data.borrow())?.amount;
+        let reserve_to = Account::unpack_from_slice(&self.pool_to_token_info.data.borrow())?.amount;
+        let (reserve_x, reserve_y) = if self.x_to_y {
+            (reserve_from, reserve_to)
+        } else {
+            (reserve_to, reserve_from)
+        };
+        let total_lp = Mint::unpack_from_slice(&self.mint_lp_token_info.data.borrow())?.supply;
+        Ok(self.pool.snapshot(reserve_x, reserve_y, total_lp))
+    }
+
+    /// Swap accounts from the `ProvideLiquidity` layout, swapping from the
+    /// `from_mint` token. Expects the layout already validated.
+    fn from_liquidity_accounts(
+        accounts: &'a [AccountInfo<'b>],
+        pool: Pool,
+        from_mint: &Pubkey,
+    ) -> Result<Self, ProgramError> {
+        let acc_iter = &mut accounts.iter();
+        let user_info = next_account_info(acc_iter)?;
+        let pool_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let x_user_token_info = next_account_info(acc_iter)?;
+        let y_user_token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let pool_x_token_info = next_account_info(acc_iter)?;
+        let pool_y_token_info = next_account_info(acc_iter)?;
+        let mint_lp_token_info = next_account_info(acc_iter)?;
+        let commision_x_token_info = next_account_info(acc_iter)?;
+        let commision_y_token_info = next_account_info(acc_iter)?;
+        let authority_info = next_account_info(acc_iter)?;
+        let token_info = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let _ = next_account_info(acc_iter)?;
+        let observations_info = next_account_info(acc_iter)?;
+
+        let x_to_y = if *from_mint == pool.mint_x {
+            true
+        } else if *from_mint == pool.mint_y {
+            false
+        } else {
+            return Err(PoolError::InvalidMint.into());
+        };
+        let (user_from, user_to, pool_from, pool_to, commision) = if x_to_y {
+            (
+                x_user_token_info,
+                y_user_token_info,
+                pool_x_token_info,
+                pool_y_token_info,
+                commision_x_token_info,
+            )
+        } else {
+            (
+                y_user_token_info,
+                x_user_token_info,
+                pool_y_token_info,
+                pool_x_token_info,
+                commision_y_token_info,
+            )
+        };
+
+        Ok(SwapAccounts {
+            user_info,
+            pool_info,
+            user_from_token_info: user_from,
+            user_to_token_info: user_to,
+            pool_from_token_info: pool_from,
+            pool_to_token_info: pool_to,
+            commision_info: commision,
+            authority_info,
+            token_info,
+            mint_lp_token_info,
+            observations_info,
+            ticks_info: None,
+            pool,
+            x_to_y,
+        })
+    }
 }
@@ -1,38 +1,449 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::pubkey::Pubkey;
 
-use crate::{id, POOL_SEED};
+use crate::pool_math::{CommisionSplit, MathError, PoolSnapshot};
+use crate::{
+    curve::CurveType, id, math, LOCKED_LP_SEED, OBSERVATIONS_SEED, POOL_SEED, POSITION_SEED,
+    STAKED_LP_SEED, TICKS_SEED,
+};
 
+pub use crate::concentrated::{Tick, Ticks};
+
+/// Fee position of an lp token account, checkpointed on every fee withdraw.
+/// Commision accrues on the lp tokens staked by the position, held in the
+/// pool staked lp account where only the program can move them, so every
+/// lp token earns for a single position at a time. Lp tokens outside of it
+/// earn nothing.
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct WithdrawedFee {
-    pub user_x_withdraw: u64,
-    pub user_y_withdraw: u64,
+    pub lp_staked: u64,
+    pub fee_growth_x_checkpoint: u128,
+    pub fee_growth_y_checkpoint: u128,
 }
 
 impl WithdrawedFee {
-    pub fn get_withdraw_pubkey_with_bump(user: &Pubkey) -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[&user.to_bytes(), POOL_SEED.as_bytes()], &id())
+    pub fn get_withdraw_pubkey_with_bump(pool: &Pubkey, lp_token: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&pool.to_bytes(), &lp_token.to_bytes(), POOL_SEED.as_bytes()],
+            &id(),
+        )
     }
 
-    pub fn get_withdraw_pubkey(user: &Pubkey) -> Pubkey {
-        let (pubkey, _) = Self::get_withdraw_pubkey_with_bump(user);
+    pub fn get_withdraw_pubkey(pool: &Pubkey, lp_token: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_withdraw_pubkey_with_bump(pool, lp_token);
         pubkey
     }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub struct TotalCommision {
-    pub total_x_commision: u64,
-    pub total_y_commision: u64,
+pub struct Pool {
+    pub admin: Pubkey,
+    pub mint_x: Pubkey,
+    pub mint_y: Pubkey,
+    pub pool_x_token: Pubkey,
+    pub pool_y_token: Pubkey,
+    pub mint_lp: Pubkey,
+    pub locked_lp_token: Pubkey,
+    pub staked_lp_token: Pubkey,
+    pub commision_x_token: Pubkey,
+    pub commision_y_token: Pubkey,
+    pub treasury_x_token: Pubkey,
+    pub treasury_y_token: Pubkey,
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+    pub protocol_fee_numerator: u64,
+    pub protocol_fee_denominator: u64,
+    /// Liquidity provider commision is added to the pool reserves instead of
+    /// the commision accounts, growing the value of lp tokens.
+    pub compound_fees: bool,
+    /// Invariant used to price swaps.
+    pub curve: CurveType,
+    /// Square root of the price of x in y, Q64.64, on a concentrated
+    /// liquidity pool.
+    pub sqrt_price: u128,
+    /// Greatest tick at or below `sqrt_price`.
+    pub tick_current: i32,
+    /// Liquidity of the positions in range of `tick_current`.
+    pub liquidity: u128,
+    /// Protocol share of the commision not yet collected to the treasury.
+    pub protocol_fee_x: u64,
+    pub protocol_fee_y: u64,
+    /// Lp tokens held by the staked lp account, the only lp tokens earning
+    /// the commision of a pool not compounding it.
+    pub staked_lp: u64,
+    /// Liquidity provider commision earned per staked lp token, or per unit
+    /// of liquidity on a concentrated liquidity pool, Q64.64.
+    pub fee_growth_x: u128,
+    pub fee_growth_y: u128,
+    /// Sums of the price of x in y and of y in x, Q64.64, multiplied by the
+    /// seconds each price was held. Both wrap on overflow.
+    pub price_x_cumulative: u128,
+    pub price_y_cumulative: u128,
+    /// Sum of the lp supply, or of the liquidity in range on a concentrated
+    /// liquidity pool, multiplied by the seconds it was held. Wraps on overflow.
+    pub liquidity_cumulative: u128,
+    /// Unix timestamp of the last accumulators update.
+    pub last_update_timestamp: i64,
+    pub bump_seed: u8,
+    pub authority_bump_seed: u8,
+}
+
+impl Pool {
+    pub fn get_pool_pubkey_with_bump(mint_x: &Pubkey, mint_y: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[&mint_x.to_bytes(), &mint_y.to_bytes(), POOL_SEED.as_bytes()],
+            &id(),
+        )
+    }
+
+    pub fn get_pool_pubkey(mint_x: &Pubkey, mint_y: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_pool_pubkey_with_bump(mint_x, mint_y);
+        pubkey
+    }
+
+    /// Authority owning the pool vaults, commision accounts and lp mint.
+    pub fn get_authority_pubkey_with_bump(pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[&pool.to_bytes(), POOL_SEED.as_bytes()], &id())
+    }
+
+    pub fn get_authority_pubkey(pool: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_authority_pubkey_with_bump(pool);
+        pubkey
+    }
+
+    /// Lp token account holding the minimum liquidity, owned by the system
+    /// program so its tokens can never be moved.
+    pub fn get_locked_lp_pubkey_with_bump(pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[&pool.to_bytes(), LOCKED_LP_SEED.as_bytes()], &id())
+    }
+
+    pub fn get_locked_lp_pubkey(pool: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_locked_lp_pubkey_with_bump(pool);
+        pubkey
+    }
+
+    /// Lp token account holding the lp tokens staked by the fee positions,
+    /// owned by the pool authority.
+    pub fn get_staked_lp_pubkey_with_bump(pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[&pool.to_bytes(), STAKED_LP_SEED.as_bytes()], &id())
+    }
+
+    pub fn get_staked_lp_pubkey(pool: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_staked_lp_pubkey_with_bump(pool);
+        pubkey
+    }
+
+    /// Snapshot of the pool with the given token balances and no ticks.
+    pub fn snapshot(&self, reserve_x: u64, reserve_y: u64, total_lp: u64) -> PoolSnapshot {
+        PoolSnapshot {
+            curve: self.curve,
+            fee_numerator: self.fee_numerator,
+            fee_denominator: self.fee_denominator,
+            protocol_fee_numerator: self.protocol_fee_numerator,
+            protocol_fee_denominator: self.protocol_fee_denominator,
+            compound_fees: self.compound_fees,
+            reserve_x,
+            reserve_y,
+            total_lp,
+            staked_lp: self.staked_lp,
+            sqrt_price: self.sqrt_price,
+            tick_current: self.tick_current,
+            liquidity: self.liquidity,
+            fee_growth_x: self.fee_growth_x,
+            fee_growth_y: self.fee_growth_y,
+            ticks: Ticks::default(),
+        }
+    }
+
+    /// Stores the concentrated liquidity price and liquidity of `snapshot`.
+    pub fn store_price(&mut self, snapshot: &PoolSnapshot) {
+        self.sqrt_price = snapshot.sqrt_price;
+        self.tick_current = snapshot.tick_current;
+        self.liquidity = snapshot.liquidity;
+    }
+
+    /// Records the protocol share and the lp fee growth of commision of the
+    /// x token, or the y token if `!is_x`, paid to the pool.
+    pub fn accrue_commision(
+        &mut self,
+        is_x: bool,
+        split: &CommisionSplit,
+    ) -> Result<(), MathError> {
+        let (protocol_fee, fee_growth) = if is_x {
+            (&mut self.protocol_fee_x, &mut self.fee_growth_x)
+        } else {
+            (&mut self.protocol_fee_y, &mut self.fee_growth_y)
+        };
+        *protocol_fee = protocol_fee
+            .checked_add(split.protocol_fee)
+            .ok_or(MathError::MathOverflow)?;
+        *fee_growth = fee_growth
+            .checked_add(split.fee_growth)
+            .ok_or(MathError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Accumulates the reserves and the lp supply held since the last
+    /// update, must be called before they change.
+    pub fn update_cumulatives(
+        &mut self,
+        reserve_x: u64,
+        reserve_y: u64,
+        total_lp: u64,
+        timestamp: i64,
+    ) {
+        let observation = self.observation_at(reserve_x, reserve_y, total_lp, timestamp);
+        self.price_x_cumulative = observation.price_x_cumulative;
+        self.price_y_cumulative = observation.price_y_cumulative;
+        self.liquidity_cumulative = observation.liquidity_cumulative;
+        self.last_update_timestamp = timestamp;
+    }
+
+    pub fn observation(&self) -> PriceObservation {
+        PriceObservation {
+            timestamp: self.last_update_timestamp,
+            price_x_cumulative: self.price_x_cumulative,
+            price_y_cumulative: self.price_y_cumulative,
+            liquidity_cumulative: self.liquidity_cumulative,
+        }
+    }
+
+    /// Marginal prices of x in y and of y in x, Q64.64. Concentrated
+    /// liquidity pools take them from `sqrt_price`, other curves from the
+    /// reserves, which must not be zero.
+    pub fn spot_prices(&self, reserve_x: u64, reserve_y: u64) -> Option<(u128, u128)> {
+        if self.curve.is_concentrated() {
+            let price_x = math::mul_div(
+                self.sqrt_price,
+                self.sqrt_price,
+                math::Q64,
+                math::Rounding::Down,
+            )?;
+            let inverse =
+                math::mul_div(math::Q64, math::Q64, self.sqrt_price, math::Rounding::Down)?;
+            let price_y = math::mul_div(inverse, inverse, math::Q64, math::Rounding::Down)?;
+            return Some((price_x, price_y));
+        }
+        if reserve_x == 0 || reserve_y == 0 {
+            return None;
+        }
+        let price_x = self
+            .curve
+            .spot_price(reserve_x, reserve_y, true)
+            .unwrap_or_else(|| math::price_q64(reserve_x, reserve_y));
+        let price_y = self
+            .curve
+            .spot_price(reserve_y, reserve_x, false)
+            .unwrap_or_else(|| math::price_q64(reserve_y, reserve_x));
+        Some((price_x, price_y))
+    }
+
+    /// Accumulators at `timestamp`, if the reserves and the lp supply have
+    /// not changed since the last update. Prices are the marginal prices of
+    /// the pool curve. Concentrated liquidity pools ignore the reserves and
+    /// the lp supply and use their own price and liquidity.
+    pub fn observation_at(
+        &self,
+        reserve_x: u64,
+        reserve_y: u64,
+        total_lp: u64,
+        timestamp: i64,
+    ) -> PriceObservation {
+        let mut observation = self.observation();
+        observation.timestamp = timestamp;
+        let elapsed = timestamp.saturating_sub(self.last_update_timestamp);
+        let prices = self
+            .spot_prices(reserve_x, reserve_y)
+            .filter(|_| elapsed > 0);
+        if let Some((price_x, price_y)) = prices {
+            let elapsed = elapsed as u128;
+            let liquidity = if self.curve.is_concentrated() {
+                self.liquidity
+            } else {
+                total_lp as u128
+            };
+            observation.price_x_cumulative = observation
+                .price_x_cumulative
+                .wrapping_add(price_x.wrapping_mul(elapsed));
+            observation.price_y_cumulative = observation
+                .price_y_cumulative
+                .wrapping_add(price_y.wrapping_mul(elapsed));
+            observation.liquidity_cumulative = observation
+                .liquidity_cumulative
+                .wrapping_add(liquidity.wrapping_mul(elapsed));
+        }
+        observation
+    }
+}
+
+/// Accumulators of a pool at a point in time.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PriceObservation {
+    pub timestamp: i64,
+    pub price_x_cumulative: u128,
+    pub price_y_cumulative: u128,
+    pub liquidity_cumulative: u128,
+}
+
+impl PriceObservation {
+    /// Time weighted average prices of x in y and of y in x, Q64.64, between
+    /// this and a later observation. `None` if no time has passed.
+    pub fn twap(&self, later: &PriceObservation) -> Option<(u128, u128)> {
+        let elapsed = later.timestamp.checked_sub(self.timestamp)?;
+        if elapsed <= 0 {
+            return None;
+        }
+        Some((
+            math::twap(
+                self.price_x_cumulative,
+                later.price_x_cumulative,
+                elapsed as u64,
+            ),
+            math::twap(
+                self.price_y_cumulative,
+                later.price_y_cumulative,
+                elapsed as u64,
+            ),
+        ))
+    }
+
+    /// Observation at `timestamp` between this and a later observation,
+    /// assuming the accumulators grew linearly in between.
+    pub fn interpolate(&self, later: &PriceObservation, timestamp: i64) -> PriceObservation {
+        let span = later.timestamp.saturating_sub(self.timestamp);
+        if span <= 0 {
+            return *self;
+        }
+        let span = span as u64;
+        let elapsed = timestamp.saturating_sub(self.timestamp) as u128;
+        let grow = |start: u128, end: u128| {
+            start.wrapping_add(math::twap(start, end, span).wrapping_mul(elapsed))
+        };
+        PriceObservation {
+            timestamp,
+            price_x_cumulative: grow(self.price_x_cumulative, later.price_x_cumulative),
+            price_y_cumulative: grow(self.price_y_cumulative, later.price_y_cumulative),
+            liquidity_cumulative: grow(self.liquidity_cumulative, later.liquidity_cumulative),
+        }
+    }
+}
+
+/// Ring buffer of pool observations, written by every trade. Slots
+/// `[0, cardinality)` are in use and `index` is the latest one, the rest of
+/// the capacity is taken once the buffer reaches it.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct Observations {
+    pub index: u16,
+    pub cardinality: u16,
+    pub observations: Vec<PriceObservation>,
+}
+
+impl Observations {
+    pub fn get_observations_pubkey_with_bump(pool: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[&pool.to_bytes(), OBSERVATIONS_SEED.as_bytes()], &id())
+    }
+
+    pub fn get_observations_pubkey(pool: &Pubkey) -> Pubkey {
+        let (pubkey, _) = Self::get_observations_pubkey_with_bump(pool);
+        pubkey
+    }
+
+    pub fn latest(&self) -> &PriceObservation {
+        &self.observations[self.index as usize]
+    }
+
+    /// Stores `observation` after the latest one, or in its place if both
+    /// have the same timestamp.
+    pub fn write(&mut self, observation: PriceObservation) {
+        if self.latest().timestamp == observation.timestamp {
+            self.observations[self.index as usize] = observation;
+            return;
+        }
+        if self.index + 1 == self.cardinality
+            && (self.cardinality as usize) < self.observations.len()
+        {
+            self.cardinality += 1;
+        }
+        self.index = (self.index + 1) % self.cardinality;
+        self.observations[self.index as usize] = observation;
+    }
+
+    /// Observation at `timestamp`, interpolated between the stored ones or
+    /// between the latest and `current`. `None` outside of that history.
+    pub fn observe(&self, current: &PriceObservation, timestamp: i64) -> Option<PriceObservation> {
+        if timestamp > current.timestamp {
+            return None;
+        }
+        let latest = self.latest();
+        if timestamp >= latest.timestamp {
+            return Some(latest.interpolate(current, timestamp));
+        }
+        let cardinality = self.cardinality as usize;
+        let oldest = (self.index as usize + 1) % cardinality;
+        let mut before: Option<&PriceObservation> = None;
+        for i in 0..cardinality {
+            let observation = &self.observations[(oldest + i) % cardinality];
+            if observation.timestamp == timestamp {
+                return Some(*observation);
+            }
+            if observation.timestamp > timestamp {
+                return before.map(|before| before.interpolate(observation, timestamp));
+            }
+            before = Some(observation);
+        }
+        None
+    }
+}
+
+/// `Ticks` lives in the `pool-math` crate, so its address helpers are free
+/// functions here.
+pub fn get_ticks_pubkey_with_bump(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[&pool.to_bytes(), TICKS_SEED.as_bytes()], &id())
+}
+
+pub fn get_ticks_pubkey(pool: &Pubkey) -> Pubkey {
+    let (pubkey, _) = get_ticks_pubkey_with_bump(pool);
+    pubkey
+}
+
+/// Concentrated liquidity position of `owner` between two ticks. Commision
+/// is paid out on every change, checkpointing the growth inside the range.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct Position {
+    pub owner: Pubkey,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: u128,
+    pub fee_growth_inside_x_checkpoint: u128,
+    pub fee_growth_inside_y_checkpoint: u128,
 }
 
-impl TotalCommision {
-    pub fn get_total_pubkey_with_bump() -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[&id().to_bytes(), POOL_SEED.as_bytes()], &id())
+impl Position {
+    pub fn get_position_pubkey_with_bump(
+        pool: &Pubkey,
+        owner: &Pubkey,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                &pool.to_bytes(),
+                &owner.to_bytes(),
+                &tick_lower.to_le_bytes(),
+                &tick_upper.to_le_bytes(),
+                POSITION_SEED.as_bytes(),
+            ],
+            &id(),
+        )
     }
 
-    pub fn get_total_pubkey() -> Pubkey {
-        let (pubkey, _) = Self::get_total_pubkey_with_bump();
+    pub fn get_position_pubkey(
+        pool: &Pubkey,
+        owner: &Pubkey,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Pubkey {
+        let (pubkey, _) = Self::get_position_pubkey_with_bump(pool, owner, tick_lower, tick_upper);
         pubkey
     }
 }
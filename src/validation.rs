@@ -0,0 +1,150 @@
+//! Account checks run by the instruction handlers before any state change
+//! or cross program invocation.
+
+use solana_program::account_info::AccountInfo;
+use solana_program::entrypoint::ProgramResult;
+use solana_program::program_error::ProgramError;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_program;
+
+use spl_token::state::{Account, Mint};
+
+use crate::error::PoolError;
+use crate::state::Pool;
+
+pub fn check_signer(account_info: &AccountInfo) -> ProgramResult {
+    if !account_info.is_signer {
+        return Err(PoolError::SignedRequired.into());
+    }
+    Ok(())
+}
+
+pub fn check_writable(account_info: &AccountInfo) -> ProgramResult {
+    if !account_info.is_writable {
+        return Err(PoolError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+pub fn check_token_program(account_info: &AccountInfo) -> ProgramResult {
+    if *account_info.key != spl_token::id() {
+        return Err(PoolError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+pub fn check_system_program(account_info: &AccountInfo) -> ProgramResult {
+    if *account_info.key != system_program::id() {
+        return Err(PoolError::InvalidSystemProgram.into());
+    }
+    Ok(())
+}
+
+/// Unpacks a token account of `mint` owned by the token program.
+pub fn check_token_account(
+    account_info: &AccountInfo,
+    mint: &Pubkey,
+) -> Result<Account, ProgramError> {
+    if *account_info.owner != spl_token::id() {
+        return Err(PoolError::InvalidAccountOwner.into());
+    }
+    let account = Account::unpack_from_slice(&account_info.data.borrow())?;
+    if account.mint != *mint {
+        return Err(PoolError::InvalidMint.into());
+    }
+    Ok(account)
+}
+
+/// Unpacks a token account of `mint` whose owner is `owner`.
+pub fn check_user_token_account(
+    account_info: &AccountInfo,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Result<Account, ProgramError> {
+    let account = check_token_account(account_info, mint)?;
+    if account.owner != *owner {
+        return Err(PoolError::InvalidTokenOwner.into());
+    }
+    Ok(account)
+}
+
+/// Unpacks a mint owned by the token program.
+pub fn check_mint(account_info: &AccountInfo) -> Result<Mint, ProgramError> {
+    if *account_info.owner != spl_token::id() {
+        return Err(PoolError::InvalidAccountOwner.into());
+    }
+    Mint::unpack_from_slice(&account_info.data.borrow())
+}
+
+/// Lp mint of a new pool, with no supply to redeem against the deposits
+/// and no freeze authority.
+pub fn check_new_lp_mint(mint: &Mint) -> ProgramResult {
+    if mint.supply != 0 {
+        return Err(PoolError::LpMintSupply.into());
+    }
+    if mint.freeze_authority.is_some() {
+        return Err(PoolError::LpMintFreezeAuthority.into());
+    }
+    Ok(())
+}
+
+/// Vault or commision account of a new pool, empty and only movable by
+/// the pool authority.
+pub fn check_new_pool_token_account(account: &Account) -> ProgramResult {
+    if account.amount != 0 {
+        return Err(PoolError::AccountNotEmpty.into());
+    }
+    if account.delegate.is_some() {
+        return Err(PoolError::AccountDelegated.into());
+    }
+    if account.close_authority.is_some() {
+        return Err(PoolError::AccountCloseAuthority.into());
+    }
+    Ok(())
+}
+
+pub fn check_vault(account_info: &AccountInfo, expected: &Pubkey) -> ProgramResult {
+    if account_info.key != expected {
+        return Err(PoolError::InvalidVault.into());
+    }
+    Ok(())
+}
+
+pub fn check_lp_mint(account_info: &AccountInfo, pool: &Pool) -> ProgramResult {
+    if *account_info.key != pool.mint_lp {
+        return Err(PoolError::InvalidLpMint.into());
+    }
+    Ok(())
+}
+
+/// Account holding the lp tokens staked by the fee positions.
+pub fn check_staked_lp(account_info: &AccountInfo, pool: &Pool) -> ProgramResult {
+    if *account_info.key != pool.staked_lp_token {
+        return Err(PoolError::InvalidStakedLiquidity.into());
+    }
+    Ok(())
+}
+
+/// Commision and treasury accounts recorded in the pool.
+pub fn check_fee_account(account_info: &AccountInfo, expected: &Pubkey) -> ProgramResult {
+    if account_info.key != expected {
+        return Err(PoolError::InvalidFeeAccount.into());
+    }
+    Ok(())
+}
+
+pub fn check_authority(account_info: &AccountInfo, pool_key: &Pubkey) -> ProgramResult {
+    if *account_info.key != Pool::get_authority_pubkey(pool_key) {
+        return Err(PoolError::InvalidAuthority.into());
+    }
+    Ok(())
+}
+
+pub fn check_admin(account_info: &AccountInfo, pool: &Pool) -> ProgramResult {
+    check_signer(account_info)?;
+    if *account_info.key != pool.admin {
+        return Err(PoolError::InvalidAdmin.into());
+    }
+    Ok(())
+}
@@ -2,7 +2,17 @@
 use std::assert_eq;
 
 use borsh::BorshDeserialize;
-use solana_program::{program_pack::Pack, pubkey::Pubkey, system_instruction};
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program::invoke,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction, system_program,
+    sysvar::clock::Clock,
+};
 use solana_program_test::{
     processor,
     tokio::{self},
@@ -11,15 +21,31 @@ use solana_program_test::{
 
 use spl_token::state::{Account, Mint};
 
-use pool::{entrypoint::process_instruction, id, instruction::PoolInstruction};
+use pool::{
+    concentrated,
+    curve::CurveType,
+    entrypoint::process_instruction,
+    error::PoolError,
+    id,
+    instruction::{
+        Deadline, DepositQuote, ObservedTwap, PoolInstruction, SwapHop, SwapQuote, WithdrawQuote,
+    },
+    math,
+    pool_math::PoolSnapshot,
+    state::{get_ticks_pubkey, Observations, Pool, Position, Ticks, WithdrawedFee},
+    MINIMUM_LIQUIDITY,
+};
 use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 
 struct Env {
     ctx: ProgramTestContext,
     admin: Keypair,
     user_01: Keypair,
     user_02: Keypair,
+    pool: Pubkey,
+    mint_x_account: Keypair,
+    mint_y_account: Keypair,
     mint_lp_account: Keypair,
     user_01_x_token_account: Keypair,
     user_01_y_token_account: Keypair,
@@ -31,11 +57,80 @@ struct Env {
     pool_y_token_account: Keypair,
     commision_x_token_account: Keypair,
     commision_y_token_account: Keypair,
+    treasury_x_token_account: Keypair,
+    treasury_y_token_account: Keypair,
+    borrower_program: Pubkey,
+}
+
+struct PoolConfig {
+    fee_numerator: u64,
+    fee_denominator: u64,
+    protocol_fee_numerator: u64,
+    protocol_fee_denominator: u64,
+    compound_fees: bool,
+    curve: CurveType,
+}
+
+/// Flash loan borrower, returns the little endian `u64` amount from the
+/// borrower token account to the pool.
+/// Accounts:
+/// 0. `[writable]` borrower token account
+/// 1. `[writable]` pool token account
+/// 2. `[signer]` borrower token account owner
+/// 3. `[]` token program
+fn flash_borrower(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let acc_iter = &mut accounts.iter();
+    let borrower_token_info = next_account_info(acc_iter)?;
+    let pool_token_info = next_account_info(acc_iter)?;
+    let owner_info = next_account_info(acc_iter)?;
+    let token_info = next_account_info(acc_iter)?;
+    let amount = u64::from_le_bytes(data.try_into().unwrap());
+
+    let repay = spl_token::instruction::transfer(
+        token_info.key,
+        borrower_token_info.key,
+        pool_token_info.key,
+        owner_info.key,
+        &[owner_info.key],
+        amount,
+    )?;
+    invoke(
+        &repay,
+        &[
+            borrower_token_info.clone(),
+            pool_token_info.clone(),
+            owner_info.clone(),
+            token_info.clone(),
+        ],
+    )
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            fee_numerator: 3,
+            fee_denominator: 1000,
+            protocol_fee_numerator: 0,
+            protocol_fee_denominator: 1,
+            compound_fees: false,
+            curve: CurveType::ConstantProduct,
+        }
+    }
 }
 
 impl Env {
     async fn new() -> Self {
-        let program_test = ProgramTest::new("pool", id(), processor!(process_instruction));
+        Self::with_config(PoolConfig::default()).await
+    }
+
+    async fn with_config(config: PoolConfig) -> Self {
+        let borrower_program = Pubkey::new_unique();
+        let mut program_test = ProgramTest::new("pool", id(), processor!(process_instruction));
+        program_test.add_program(
+            "flash_borrower",
+            borrower_program,
+            processor!(flash_borrower),
+        );
         let mut ctx = program_test.start_with_context().await;
 
         let admin = Keypair::new();
@@ -71,13 +166,19 @@ impl Env {
         let mint_x_account = Keypair::new();
         let mint_y_account = Keypair::new();
         let mint_lp_account = Keypair::new();
-        let mint_array = [&mint_x_account, &mint_y_account, &mint_lp_account];
+        let pool = Pool::get_pool_pubkey(&mint_x_account.pubkey(), &mint_y_account.pubkey());
+        let pool_authority = Pool::get_authority_pubkey(&pool);
+        let mint_array = [
+            (&mint_x_account, admin.pubkey()),
+            (&mint_y_account, admin.pubkey()),
+            (&mint_lp_account, pool_authority),
+        ];
 
         let token_program = &spl_token::id();
         let rent = ctx.banks_client.get_rent().await.unwrap();
         let mint_rent = rent.minimum_balance(Mint::LEN);
 
-        for i in mint_array {
+        for (i, mint_authority) in mint_array {
             let token_mint_account_ix = solana_program::system_instruction::create_account(
                 &ctx.payer.pubkey(),
                 &i.pubkey(),
@@ -89,7 +190,7 @@ impl Env {
             let token_mint_a_ix = spl_token::instruction::initialize_mint(
                 token_program,
                 &i.pubkey(),
-                &admin.pubkey(),
+                &mint_authority,
                 None,
                 9,
             )
@@ -114,6 +215,8 @@ impl Env {
         let user_02_x_token_account = Keypair::new();
         let user_02_y_token_account = Keypair::new();
         let user_02_lp_token_account = Keypair::new();
+        let treasury_x_token_account = Keypair::new();
+        let treasury_y_token_account = Keypair::new();
         let user_wallets = [
             [&user_01_x_token_account, &mint_x_account, &user_01],
             [&user_01_y_token_account, &mint_y_account, &user_01],
@@ -121,6 +224,8 @@ impl Env {
             [&user_02_x_token_account, &mint_x_account, &user_02],
             [&user_02_y_token_account, &mint_y_account, &user_02],
             [&user_02_lp_token_account, &mint_lp_account, &user_02],
+            [&treasury_x_token_account, &mint_x_account, &admin],
+            [&treasury_y_token_account, &mint_y_account, &admin],
         ];
         let account_rent = rent.minimum_balance(Account::LEN);
 
@@ -175,7 +280,7 @@ impl Env {
                 token_program,
                 &i.pubkey(),
                 &j.pubkey(),
-                &admin.pubkey(),
+                &pool_authority,
             )
             .unwrap();
 
@@ -213,7 +318,7 @@ impl Env {
                 token_program,
                 &i.pubkey(),
                 &j.pubkey(),
-                &admin.pubkey(),
+                &pool_authority,
             )
             .unwrap();
 
@@ -259,11 +364,40 @@ impl Env {
                 .unwrap();
         }
 
+        let tx = Transaction::new_signed_with_payer(
+            &[PoolInstruction::initialize_pool(
+                &admin.pubkey(),
+                &mint_x_account.pubkey(),
+                &mint_y_account.pubkey(),
+                &pool_x_token_account.pubkey(),
+                &pool_y_token_account.pubkey(),
+                &mint_lp_account.pubkey(),
+                &commision_x_token_account.pubkey(),
+                &commision_y_token_account.pubkey(),
+                &treasury_x_token_account.pubkey(),
+                &treasury_y_token_account.pubkey(),
+                config.fee_numerator,
+                config.fee_denominator,
+                config.protocol_fee_numerator,
+                config.protocol_fee_denominator,
+                config.compound_fees,
+                config.curve,
+            )],
+            Some(&admin.pubkey()),
+            &[&admin],
+            ctx.last_blockhash,
+        );
+
+        ctx.banks_client.process_transaction(tx).await.unwrap();
+
         Env {
             ctx,
             admin,
             user_01,
             user_02,
+            pool,
+            mint_x_account,
+            mint_y_account,
             mint_lp_account,
             user_01_x_token_account,
             user_01_y_token_account,
@@ -275,6 +409,9 @@ impl Env {
             pool_y_token_account,
             commision_x_token_account,
             commision_y_token_account,
+            treasury_x_token_account,
+            treasury_y_token_account,
+            borrower_program,
         }
     }
 }
@@ -287,7 +424,7 @@ async fn provide_liquidity_first() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -296,38 +433,49 @@ async fn provide_liquidity_first() {
             &env.mint_lp_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
             &env.commision_y_token_account.pubkey(),
-            5,
-            15,
+            5000,
+            15000,
+            0,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
     env.ctx.banks_client.process_transaction(tx).await.unwrap();
 
+    let user_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
+
     let acc = env
         .ctx
         .banks_client
-        .get_account(env.user_01_lp_token_account.pubkey())
+        .get_account(env.mint_lp_account.pubkey())
         .await
         .unwrap()
         .unwrap();
 
-    let user_lp = Account::unpack_from_slice(&acc.data.as_slice()).unwrap();
+    let total_lp = Mint::unpack_from_slice(&acc.data.as_slice()).unwrap();
 
     let acc = env
         .ctx
         .banks_client
-        .get_account(env.mint_lp_account.pubkey())
+        .get_account(Pool::get_locked_lp_pubkey(&env.pool))
         .await
         .unwrap()
         .unwrap();
 
-    let total_lp = Mint::unpack_from_slice(&acc.data.as_slice()).unwrap();
+    let locked_lp = Account::unpack_from_slice(&acc.data.as_slice()).unwrap();
 
-    assert_eq!(user_lp.amount, 8);
-    assert_eq!(total_lp.supply, 8);
+    // sqrt(5000 * 15000) = 8660, minimum liquidity is locked
+    assert_eq!(user_lp, 7660);
+    assert_eq!(locked_lp.amount, 1000);
+    assert_eq!(total_lp.supply, 8660);
 }
 
 // withdraw part of user`s liquidity
@@ -338,7 +486,7 @@ async fn part_liquidity_withdraw() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -347,11 +495,13 @@ async fn part_liquidity_withdraw() {
             &env.mint_lp_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
             &env.commision_y_token_account.pubkey(),
-            5,
-            15,
+            5000,
+            15000,
+            0,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -370,7 +520,7 @@ async fn part_liquidity_withdraw() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::withdraw_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -379,24 +529,24 @@ async fn part_liquidity_withdraw() {
             &env.mint_lp_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
             &env.commision_y_token_account.pubkey(),
-            5,
+            5000,
+            2886,
+            8660,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
     env.ctx.banks_client.process_transaction(tx).await.unwrap();
 
-    let acc = env
-        .ctx
-        .banks_client
-        .get_account(env.user_01_lp_token_account.pubkey())
-        .await
-        .unwrap()
-        .unwrap();
-
-    let user_lp = Account::unpack_from_slice(&acc.data.as_slice()).unwrap();
+    let user_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
 
     let acc = env
         .ctx
@@ -420,9 +570,9 @@ async fn part_liquidity_withdraw() {
 
     let withdraw_user_x_token = user_x_token_new.amount - user_x_token_start.amount;
 
-    assert_eq!(user_lp.amount, 3);
-    assert_eq!(total_lp.supply, 3);
-    assert_eq!(withdraw_user_x_token, 3);
+    assert_eq!(user_lp, 2660);
+    assert_eq!(total_lp.supply, 3660);
+    assert_eq!(withdraw_user_x_token, 2886);
 }
 
 // test of tokens swap
@@ -433,7 +583,7 @@ async fn swap() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -442,11 +592,13 @@ async fn swap() {
             &env.mint_lp_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
             &env.commision_y_token_account.pubkey(),
-            5,
-            15,
+            5000,
+            15000,
+            0,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -475,16 +627,19 @@ async fn swap() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::swap_tokens(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.pool_x_token_account.pubkey(),
             &env.pool_y_token_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
-            13,
+            &env.mint_lp_account.pubkey(),
+            13000,
+            32598,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -513,8 +668,9 @@ async fn swap() {
     let swap_y: i64 = user_y_new.amount as i64 - user_y_start.amount as i64;
     let swap_x: i64 = user_x_new.amount as i64 - user_x_start.amount as i64;
 
-    assert_eq!(swap_y, 13);
-    assert_eq!(swap_x, -32);
+    // price 13000 * 5000 / 2000 = 32500 and commision 97.5 rounded up
+    assert_eq!(swap_y, 13000);
+    assert_eq!(swap_x, -32598);
 }
 
 // user first time withdraw commision
@@ -525,7 +681,7 @@ async fn withdraw_fee_first() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -536,9 +692,11 @@ async fn withdraw_fee_first() {
             &env.commision_y_token_account.pubkey(),
             500000,
             750000,
+            0,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -547,16 +705,19 @@ async fn withdraw_fee_first() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::swap_tokens(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.pool_x_token_account.pubkey(),
             &env.pool_y_token_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
             250000,
+            250750,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -577,7 +738,7 @@ async fn withdraw_fee_first() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::withdraw_fee(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -588,7 +749,7 @@ async fn withdraw_fee_first() {
             &env.commision_y_token_account.pubkey(),
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -608,7 +769,8 @@ async fn withdraw_fee_first() {
 
     let get_commision = user_x_new - user_x_start;
 
-    assert_eq!(get_commision, 750);
+    // commision per lp token is rounded down, dust stays in the commision account
+    assert_eq!(get_commision, 749);
 }
 
 // user withdraw commision second time, but new commision don`t arrived yet
@@ -619,7 +781,7 @@ async fn withdraw_fee_second_time() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -630,9 +792,11 @@ async fn withdraw_fee_second_time() {
             &env.commision_y_token_account.pubkey(),
             500000,
             750000,
+            0,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -641,16 +805,19 @@ async fn withdraw_fee_second_time() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::swap_tokens(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.pool_x_token_account.pubkey(),
             &env.pool_y_token_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
             250000,
+            250750,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -659,7 +826,7 @@ async fn withdraw_fee_second_time() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::withdraw_fee(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -670,7 +837,7 @@ async fn withdraw_fee_second_time() {
             &env.commision_y_token_account.pubkey(),
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -691,7 +858,7 @@ async fn withdraw_fee_second_time() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::withdraw_fee(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -702,7 +869,7 @@ async fn withdraw_fee_second_time() {
             &env.commision_y_token_account.pubkey(),
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -733,7 +900,7 @@ async fn provide_new_liquidity() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -744,9 +911,11 @@ async fn provide_new_liquidity() {
             &env.commision_y_token_account.pubkey(),
             50000,
             75000,
+            0,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -755,16 +924,19 @@ async fn provide_new_liquidity() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::swap_tokens(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.pool_x_token_account.pubkey(),
             &env.pool_y_token_account.pubkey(),
             &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
             25000,
+            25075,
+            None,
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -773,7 +945,7 @@ async fn provide_new_liquidity() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_02.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_02_x_token_account.pubkey(),
             &env.user_02_y_token_account.pubkey(),
             &env.user_02_lp_token_account.pubkey(),
@@ -784,9 +956,11 @@ async fn provide_new_liquidity() {
             &env.commision_y_token_account.pubkey(),
             75000,
             50000,
+            0,
+            None,
         )],
         Some(&env.user_02.pubkey()),
-        &[&env.user_02, &env.admin],
+        &[&env.user_02],
         env.ctx.last_blockhash,
     );
 
@@ -795,7 +969,7 @@ async fn provide_new_liquidity() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::withdraw_fee(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -806,7 +980,7 @@ async fn provide_new_liquidity() {
             &env.commision_y_token_account.pubkey(),
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -815,7 +989,7 @@ async fn provide_new_liquidity() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::provide_liquidity(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -826,6 +1000,8 @@ async fn provide_new_liquidity() {
             &env.commision_y_token_account.pubkey(),
             75000,
             50000,
+            0,
+            None,
         )],
         Some(&env.admin.pubkey()),
         &[&env.user_01, &env.admin],
@@ -849,7 +1025,7 @@ async fn provide_new_liquidity() {
     let tx = Transaction::new_signed_with_payer(
         &[PoolInstruction::withdraw_fee(
             &env.user_01.pubkey(),
-            &env.admin.pubkey(),
+            &env.pool,
             &env.user_01_x_token_account.pubkey(),
             &env.user_01_y_token_account.pubkey(),
             &env.user_01_lp_token_account.pubkey(),
@@ -860,7 +1036,7 @@ async fn provide_new_liquidity() {
             &env.commision_y_token_account.pubkey(),
         )],
         Some(&env.user_01.pubkey()),
-        &[&env.user_01, &env.admin],
+        &[&env.user_01],
         env.ctx.last_blockhash,
     );
 
@@ -882,3 +1058,4288 @@ async fn provide_new_liquidity() {
 
     assert_eq!(get_commision, 0);
 }
+
+// pool state records the accounts passed to initialization
+#[tokio::test]
+async fn initialize_pool() {
+    let mut env = Env::new().await;
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pool = Pool::try_from_slice(&acc.data.as_slice()).unwrap();
+
+    assert_eq!(acc.owner, id());
+    assert_eq!(pool.admin, env.admin.pubkey());
+    assert_eq!(pool.mint_x, env.mint_x_account.pubkey());
+    assert_eq!(pool.mint_y, env.mint_y_account.pubkey());
+    assert_eq!(pool.pool_x_token, env.pool_x_token_account.pubkey());
+    assert_eq!(pool.pool_y_token, env.pool_y_token_account.pubkey());
+    assert_eq!(pool.mint_lp, env.mint_lp_account.pubkey());
+    assert_eq!(
+        pool.commision_x_token,
+        env.commision_x_token_account.pubkey()
+    );
+    assert_eq!(
+        pool.commision_y_token,
+        env.commision_y_token_account.pubkey()
+    );
+    assert_eq!(pool.fee_numerator, 3);
+    assert_eq!(pool.fee_denominator, 1000);
+    assert_eq!(pool.curve, CurveType::ConstantProduct);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::initialize_pool(
+            &env.admin.pubkey(),
+            &env.mint_x_account.pubkey(),
+            &env.mint_y_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.treasury_x_token_account.pubkey(),
+            &env.treasury_y_token_account.pubkey(),
+            1000,
+            1000,
+            0,
+            1,
+            false,
+            CurveType::ConstantProduct,
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidFee as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::initialize_pool(
+            &env.admin.pubkey(),
+            &env.mint_x_account.pubkey(),
+            &env.mint_y_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.treasury_x_token_account.pubkey(),
+            &env.treasury_y_token_account.pubkey(),
+            3,
+            1000,
+            0,
+            1,
+            false,
+            CurveType::StableSwap { amp: 0 },
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidCurve as u32)
+        )
+    );
+}
+
+/// Overwrites the token account or mint at `address` with `state`.
+async fn set_token_state<T: Pack>(ctx: &mut ProgramTestContext, address: &Pubkey, state: T) {
+    let mut account = ctx
+        .banks_client
+        .get_account(*address)
+        .await
+        .unwrap()
+        .unwrap();
+    T::pack(state, &mut account.data).unwrap();
+    ctx.set_account(address, &account.into());
+}
+
+// initialization rejects an lp mint with supply and pool accounts someone else can move
+#[tokio::test]
+async fn initialize_pool_used_accounts() {
+    let mut env = Env::new().await;
+
+    let mint_z_account = Keypair::new();
+    let mint_lp_account = Keypair::new();
+    let pool = Pool::get_pool_pubkey(&env.mint_x_account.pubkey(), &mint_z_account.pubkey());
+    let pool_authority = Pool::get_authority_pubkey(&pool);
+    let admin = env.admin.pubkey();
+    create_mint(&mut env.ctx, &mint_z_account, &admin).await;
+    create_mint(&mut env.ctx, &mint_lp_account, &pool_authority).await;
+
+    let pool_x_token_account = Keypair::new();
+    let pool_z_token_account = Keypair::new();
+    let commision_x_token_account = Keypair::new();
+    let commision_z_token_account = Keypair::new();
+    let treasury_z_token_account = Keypair::new();
+    let mint_x = env.mint_x_account.pubkey();
+    let mint_z = mint_z_account.pubkey();
+    for (account, mint, owner) in [
+        (&pool_x_token_account, &mint_x, &pool_authority),
+        (&pool_z_token_account, &mint_z, &pool_authority),
+        (&commision_x_token_account, &mint_x, &pool_authority),
+        (&commision_z_token_account, &mint_z, &pool_authority),
+        (&treasury_z_token_account, &mint_z, &admin),
+    ] {
+        create_token_account(&mut env.ctx, account, mint, owner).await;
+    }
+
+    // a distinct fee for every attempt, so no transaction is a retry of another
+    let initialize = |fee_numerator: u64| {
+        PoolInstruction::initialize_pool(
+            &admin,
+            &mint_x,
+            &mint_z,
+            &pool_x_token_account.pubkey(),
+            &pool_z_token_account.pubkey(),
+            &mint_lp_account.pubkey(),
+            &commision_x_token_account.pubkey(),
+            &commision_z_token_account.pubkey(),
+            &env.treasury_x_token_account.pubkey(),
+            &treasury_z_token_account.pubkey(),
+            fee_numerator,
+            1000,
+            0,
+            1,
+            false,
+            CurveType::ConstantProduct,
+        )
+    };
+
+    let mint_lp = mint_lp_account.pubkey();
+    let clean_mint_lp = get_mint(&mut env.ctx, mint_lp).await;
+    let mut cases = vec![
+        (
+            Mint {
+                supply: 1,
+                ..clean_mint_lp
+            },
+            None,
+            PoolError::LpMintSupply,
+        ),
+        (
+            Mint {
+                freeze_authority: COption::Some(admin),
+                ..clean_mint_lp
+            },
+            None,
+            PoolError::LpMintFreezeAuthority,
+        ),
+    ];
+    for token_account in [
+        &pool_x_token_account,
+        &pool_z_token_account,
+        &commision_x_token_account,
+        &commision_z_token_account,
+    ] {
+        let address = token_account.pubkey();
+        let clean = get_token_account(&mut env.ctx, address).await;
+        for (state, error) in [
+            (Account { amount: 1, ..clean }, PoolError::AccountNotEmpty),
+            (
+                Account {
+                    delegate: COption::Some(admin),
+                    delegated_amount: 1,
+                    ..clean
+                },
+                PoolError::AccountDelegated,
+            ),
+            (
+                Account {
+                    close_authority: COption::Some(admin),
+                    ..clean
+                },
+                PoolError::AccountCloseAuthority,
+            ),
+        ] {
+            cases.push((clean_mint_lp, Some((address, state, clean)), error));
+        }
+    }
+
+    let cases_len = cases.len() as u64;
+    for (fee_numerator, (mint_lp_state, token_state, error)) in (1..).zip(cases) {
+        set_token_state(&mut env.ctx, &mint_lp, mint_lp_state).await;
+        if let Some((address, state, _)) = token_state {
+            set_token_state(&mut env.ctx, &address, state).await;
+        }
+
+        let tx = Transaction::new_signed_with_payer(
+            &[initialize(fee_numerator)],
+            Some(&admin),
+            &[&env.admin],
+            env.ctx.last_blockhash,
+        );
+        let err = env
+            .ctx
+            .banks_client
+            .process_transaction(tx)
+            .await
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+        );
+
+        set_token_state(&mut env.ctx, &mint_lp, clean_mint_lp).await;
+        if let Some((address, _, clean)) = token_state {
+            set_token_state(&mut env.ctx, &address, clean).await;
+        }
+    }
+
+    let tx = Transaction::new_signed_with_payer(
+        &[initialize(cases_len + 1)],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+// lamports sent to the pool addresses before initialization block nothing
+#[tokio::test]
+async fn initialize_pool_prefunded() {
+    let mut env = Env::new().await;
+
+    let mint_z_account = Keypair::new();
+    let mint_lp_account = Keypair::new();
+    let pool = Pool::get_pool_pubkey(&env.mint_x_account.pubkey(), &mint_z_account.pubkey());
+    let pool_authority = Pool::get_authority_pubkey(&pool);
+    let admin = env.admin.pubkey();
+    create_mint(&mut env.ctx, &mint_z_account, &admin).await;
+    create_mint(&mut env.ctx, &mint_lp_account, &pool_authority).await;
+
+    let pool_x_token_account = Keypair::new();
+    let pool_z_token_account = Keypair::new();
+    let commision_x_token_account = Keypair::new();
+    let commision_z_token_account = Keypair::new();
+    let treasury_z_token_account = Keypair::new();
+    let mint_x = env.mint_x_account.pubkey();
+    let mint_z = mint_z_account.pubkey();
+    for (account, mint, owner) in [
+        (&pool_x_token_account, &mint_x, &pool_authority),
+        (&pool_z_token_account, &mint_z, &pool_authority),
+        (&commision_x_token_account, &mint_x, &pool_authority),
+        (&commision_z_token_account, &mint_z, &pool_authority),
+        (&treasury_z_token_account, &mint_z, &admin),
+    ] {
+        create_token_account(&mut env.ctx, account, mint, owner).await;
+    }
+
+    // the rent of an empty account, less than the rent of the pool and
+    // observations, and more than the rent of the lp token accounts
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let empty = rent.minimum_balance(0);
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&admin, &pool, empty),
+            system_instruction::transfer(&admin, &Pool::get_locked_lp_pubkey(&pool), 10000000),
+            system_instruction::transfer(&admin, &Pool::get_staked_lp_pubkey(&pool), 10000000),
+            system_instruction::transfer(
+                &admin,
+                &Observations::get_observations_pubkey(&pool),
+                empty,
+            ),
+        ],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::initialize_pool(
+            &admin,
+            &mint_x,
+            &mint_z,
+            &pool_x_token_account.pubkey(),
+            &pool_z_token_account.pubkey(),
+            &mint_lp_account.pubkey(),
+            &commision_x_token_account.pubkey(),
+            &commision_z_token_account.pubkey(),
+            &env.treasury_x_token_account.pubkey(),
+            &treasury_z_token_account.pubkey(),
+            3,
+            1000,
+            0,
+            1,
+            false,
+            CurveType::ConstantProduct,
+        )],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let pool_account = env
+        .ctx
+        .banks_client
+        .get_account(pool)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pool_account.owner, id());
+    let pool_state = Pool::try_from_slice(&pool_account.data).unwrap();
+    assert_eq!(pool_state.mint_y, mint_z);
+    let staked_lp = get_token_account(&mut env.ctx, pool_state.staked_lp_token).await;
+    assert_eq!(staked_lp.mint, mint_lp_account.pubkey());
+    assert_eq!(staked_lp.amount, 0);
+    let locked_lp = get_token_account(&mut env.ctx, pool_state.locked_lp_token).await;
+    assert_eq!(locked_lp.mint, mint_lp_account.pubkey());
+    let observations = env
+        .ctx
+        .banks_client
+        .get_account(Observations::get_observations_pubkey(&pool))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(observations.owner, id());
+}
+
+// swap through accounts that are not the pool vaults
+#[tokio::test]
+async fn swap_wrong_pool_account() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            13,
+            34,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidVault as u32)
+        )
+    );
+}
+
+// test of tokens swap with exact input amount
+#[tokio::test]
+async fn swap_exact_in() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_x_start = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250750,
+            250000,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_y_new = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_x_new = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.commision_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let commision_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    assert_eq!(user_y_new.amount, 10000000 + 250000);
+    assert_eq!(user_x_start.amount - user_x_new.amount, 250750);
+    assert_eq!(commision_x.amount, 750);
+}
+
+// swap fails when the price is above the user`s bound
+#[tokio::test]
+async fn swap_slippage_exceeded() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250000,
+            250749,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::SlippageExceeded as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250750,
+            250001,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::SlippageExceeded as u32)
+        )
+    );
+}
+
+// liquidity is taken at the pool ratio, the rest stays with the user
+#[tokio::test]
+async fn provide_liquidity_off_ratio() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            611372,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            100000,
+            300000,
+            122475,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::SlippageExceeded as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            100000,
+            300000,
+            122474,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_02_lp_token_account.pubkey(),
+    )
+    .await;
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_y = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    assert_eq!(user_lp, 122474);
+    assert_eq!(user_y.amount, 10000000 - 150000);
+}
+
+// protocol share of commision is kept from liquidity providers and collected by admin
+#[tokio::test]
+async fn collect_protocol_fees() {
+    let mut env = Env::with_config(PoolConfig {
+        protocol_fee_numerator: 1,
+        protocol_fee_denominator: 6,
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250000,
+            250750,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::collect_protocol_fees(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.treasury_x_token_account.pubkey(),
+            &env.treasury_y_token_account.pubkey(),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidAdmin as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_fee(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::collect_protocol_fees(
+            &env.admin.pubkey(),
+            &env.pool,
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.treasury_x_token_account.pubkey(),
+            &env.treasury_y_token_account.pubkey(),
+        )],
+        Some(&env.admin.pubkey()),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.treasury_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let treasury_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.commision_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let commision_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    // only the commision per staked lp token rounding stays behind
+    assert_eq!(user_x.amount, 10000000 - 500000 + 624);
+    assert_eq!(treasury_x.amount, 125);
+    assert_eq!(commision_x.amount, 1);
+}
+
+// compounding pool keeps commision in the reserves, no withdraw account is created
+#[tokio::test]
+async fn swap_compound_fees() {
+    let mut env = Env::with_config(PoolConfig {
+        compound_fees: true,
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let withdraw = env
+        .ctx
+        .banks_client
+        .get_account(WithdrawedFee::get_withdraw_pubkey(
+            &env.pool,
+            &env.user_01_lp_token_account.pubkey(),
+        ))
+        .await
+        .unwrap();
+
+    assert!(withdraw.is_none());
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250000,
+            250750,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pool_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.commision_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let commision_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    assert_eq!(pool_x.amount, 500000 + 250000 + 750);
+    assert_eq!(commision_x.amount, 0);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_fee(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::FeesCompounded as u32)
+        )
+    );
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_lp_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_lp = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            user_lp.amount,
+            0,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    // everything except the locked minimum liquidity share
+    assert_eq!(user_x.amount, 10000000 - 500000 + 749524);
+}
+
+// lp tokens moved to another wallet do not carry commision earned before the transfer
+#[tokio::test]
+async fn withdraw_fee_after_lp_transfer() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250000,
+            250750,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &move_staked_lp(
+            &env,
+            [&env.user_01, &env.user_02],
+            [&env.user_01_x_token_account, &env.user_02_x_token_account],
+            [&env.user_01_y_token_account, &env.user_02_y_token_account],
+            [&env.user_01_lp_token_account, &env.user_02_lp_token_account],
+            306186,
+        ),
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01, &env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    for (user, x_token, y_token, lp_token) in [
+        (
+            &env.user_01,
+            &env.user_01_x_token_account,
+            &env.user_01_y_token_account,
+            &env.user_01_lp_token_account,
+        ),
+        (
+            &env.user_02,
+            &env.user_02_x_token_account,
+            &env.user_02_y_token_account,
+            &env.user_02_lp_token_account,
+        ),
+    ] {
+        let tx = Transaction::new_signed_with_payer(
+            &[PoolInstruction::withdraw_fee(
+                &user.pubkey(),
+                &env.pool,
+                &x_token.pubkey(),
+                &y_token.pubkey(),
+                &lp_token.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.commision_y_token_account.pubkey(),
+            )],
+            Some(&user.pubkey()),
+            &[user],
+            env.ctx.last_blockhash,
+        );
+
+        env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    }
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_01_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_02_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    // user_01 is paid for the past swap when unstaking, user_02 earns nothing
+    // for it
+    assert_eq!(user_01_x.amount, 10000000 - 500000 + 749);
+    assert_eq!(user_02_x.amount, 10000000 - 250750);
+    assert_eq!(
+        get_staked_lp(
+            &mut env.ctx,
+            &env.pool,
+            &env.user_02_lp_token_account.pubkey()
+        )
+        .await,
+        306186
+    );
+}
+
+// unstakes `amount` lp tokens of the first user, transfers them to the second
+// user and stakes them there
+fn move_staked_lp(
+    env: &Env,
+    users: [&Keypair; 2],
+    x_tokens: [&Keypair; 2],
+    y_tokens: [&Keypair; 2],
+    lp_tokens: [&Keypair; 2],
+    amount: u64,
+) -> Vec<Instruction> {
+    vec![
+        PoolInstruction::unstake_lp(
+            &users[0].pubkey(),
+            &env.pool,
+            &x_tokens[0].pubkey(),
+            &y_tokens[0].pubkey(),
+            &lp_tokens[0].pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            amount,
+        ),
+        spl_token::instruction::transfer(
+            &spl_token::id(),
+            &lp_tokens[0].pubkey(),
+            &lp_tokens[1].pubkey(),
+            &users[0].pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap(),
+        PoolInstruction::stake_lp(
+            &users[1].pubkey(),
+            &env.pool,
+            &x_tokens[1].pubkey(),
+            &y_tokens[1].pubkey(),
+            &lp_tokens[1].pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            amount,
+        ),
+    ]
+}
+
+// lp tokens moved back and forth between two users are paid the commision of
+// every swap at most once
+#[tokio::test]
+async fn withdraw_fee_lp_back_and_forth() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
+    let mut accrued = 0;
+    let mut paid = 0;
+
+    for round in 0..4 {
+        let commision_x =
+            get_token_balance(&mut env.ctx, env.commision_x_token_account.pubkey()).await;
+        let tx = Transaction::new_signed_with_payer(
+            &[PoolInstruction::swap_tokens(
+                &env.user_02.pubkey(),
+                &env.pool,
+                &env.user_02_x_token_account.pubkey(),
+                &env.user_02_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                10000 + round,
+                20000,
+                None,
+            )],
+            Some(&env.user_02.pubkey()),
+            &[&env.user_02],
+            env.ctx.last_blockhash,
+        );
+        env.ctx.banks_client.process_transaction(tx).await.unwrap();
+        accrued += get_token_balance(&mut env.ctx, env.commision_x_token_account.pubkey()).await
+            - commision_x;
+
+        let (from, to) = if round % 2 == 0 { (0, 1) } else { (1, 0) };
+        let users = [&env.user_01, &env.user_02];
+        let x_tokens = [&env.user_01_x_token_account, &env.user_02_x_token_account];
+        let y_tokens = [&env.user_01_y_token_account, &env.user_02_y_token_account];
+        let lp_tokens = [&env.user_01_lp_token_account, &env.user_02_lp_token_account];
+
+        let x_before = [
+            get_token_balance(&mut env.ctx, x_tokens[0].pubkey()).await,
+            get_token_balance(&mut env.ctx, x_tokens[1].pubkey()).await,
+        ];
+
+        // the lp tokens are moved and both users try to withdraw the commision
+        // of the past swap, a different amount every round keeps the
+        // transactions distinct
+        let mut instructions = move_staked_lp(
+            &env,
+            [users[from], users[to]],
+            [x_tokens[from], x_tokens[to]],
+            [y_tokens[from], y_tokens[to]],
+            [lp_tokens[from], lp_tokens[to]],
+            lp - round,
+        );
+        for i in [from, to] {
+            instructions.push(PoolInstruction::withdraw_fee(
+                &users[i].pubkey(),
+                &env.pool,
+                &x_tokens[i].pubkey(),
+                &y_tokens[i].pubkey(),
+                &lp_tokens[i].pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.commision_y_token_account.pubkey(),
+            ));
+        }
+        let tx = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&users[from].pubkey()),
+            &[users[from], users[to]],
+            env.ctx.last_blockhash,
+        );
+        env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+        for i in 0..2 {
+            paid += get_token_balance(&mut env.ctx, x_tokens[i].pubkey()).await - x_before[i];
+        }
+        assert!(paid <= accrued);
+    }
+
+    // only rounding stays behind
+    assert!(paid > 0);
+    assert!(accrued - paid <= 4);
+}
+
+// commision is shared by the staked lp tokens only, unstaked and locked lp
+// tokens leave none of it behind
+#[tokio::test]
+async fn withdraw_fee_with_unstaked_lp() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::unstake_lp(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            300000,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert_eq!(get_pool(&mut env).await.staked_lp, 611372 - 300000);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250000,
+            250750,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let accrued = get_token_balance(&mut env.ctx, env.commision_x_token_account.pubkey()).await;
+    let user_01_x = get_token_balance(&mut env.ctx, env.user_01_x_token_account.pubkey()).await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_fee(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let paid =
+        get_token_balance(&mut env.ctx, env.user_01_x_token_account.pubkey()).await - user_01_x;
+    assert!(accrued > 0);
+    assert!(accrued - paid <= 1);
+}
+
+// swap rejects a fake token program and a user account of the wrong mint
+#[tokio::test]
+async fn swap_invalid_accounts() {
+    let mut env = Env::new().await;
+
+    let mut ix = PoolInstruction::swap_tokens(
+        &env.user_01.pubkey(),
+        &env.pool,
+        &env.user_01_x_token_account.pubkey(),
+        &env.user_01_y_token_account.pubkey(),
+        &env.pool_x_token_account.pubkey(),
+        &env.pool_y_token_account.pubkey(),
+        &env.commision_x_token_account.pubkey(),
+        &env.mint_lp_account.pubkey(),
+        13,
+        34,
+        None,
+    );
+    ix.accounts[8].pubkey = system_program::id();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidTokenProgram as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            13,
+            34,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidMint as u32)
+        )
+    );
+}
+
+// lp tokens can not be minted to an lp account of another user
+#[tokio::test]
+async fn provide_liquidity_foreign_lp_account() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_02_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidTokenOwner as u32)
+        )
+    );
+}
+
+// amounts overflowing the pool reserves fail with a custom error instead of a panic
+#[tokio::test]
+async fn swap_exact_in_overflow() {
+    let mut env = Env::with_config(PoolConfig {
+        fee_numerator: 0,
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            u64::MAX,
+            0,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::MathOverflow as u32)
+        )
+    );
+}
+
+// first deposit must mint more than the locked minimum liquidity
+#[tokio::test]
+async fn provide_liquidity_below_minimum() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500,
+            2000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::ZeroLiquidity as u32)
+        )
+    );
+}
+
+// swap after the deadline is rejected
+#[tokio::test]
+async fn swap_expired() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            Some(Deadline::Timestamp(i64::MAX)),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    env.ctx.warp_to_slot(100).unwrap();
+
+    for deadline in [Deadline::Timestamp(0), Deadline::Slot(99)] {
+        let tx = Transaction::new_signed_with_payer(
+            &[PoolInstruction::swap_tokens(
+                &env.user_02.pubkey(),
+                &env.pool,
+                &env.user_02_x_token_account.pubkey(),
+                &env.user_02_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                250000,
+                250750,
+                Some(deadline),
+            )],
+            Some(&env.user_02.pubkey()),
+            &[&env.user_02],
+            env.ctx.last_blockhash,
+        );
+
+        let err = env
+            .ctx
+            .banks_client
+            .process_transaction(tx)
+            .await
+            .unwrap_err()
+            .unwrap();
+
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PoolError::Expired as u32)
+            )
+        );
+    }
+
+    // the current slot is not past the deadline yet
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250000,
+            250750,
+            Some(Deadline::Slot(100)),
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+// single sided deposit swaps part of the token and provides the rest at the pool ratio
+#[tokio::test]
+async fn provide_single_sided() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_single_sided(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.mint_x_account.pubkey(),
+            100000,
+            58367,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::SlippageExceeded as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_single_sided(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.mint_x_account.pubkey(),
+            100000,
+            58366,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_02_lp_token_account.pubkey(),
+    )
+    .await;
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_y = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.commision_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let commision_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    // 47800 x is swapped for 65264 y, all of which is provided back
+    assert_eq!(user_lp, 58366);
+    assert_eq!(user_x.amount, 10000000 - 99998);
+    assert_eq!(user_y.amount, 10000000);
+    assert_eq!(commision_x.amount, 143);
+}
+
+// single sided withdraw swaps the y half and pays out x only
+#[tokio::test]
+async fn withdraw_single_sided() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_single_sided(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            100000,
+            &env.mint_x_account.pubkey(),
+            149794,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::SlippageExceeded as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_single_sided(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            100000,
+            &env.mint_x_account.pubkey(),
+            149793,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_x = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let user_y = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.commision_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let commision_y = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+
+    // 81649 x withdrawn and 122474 y swapped for 68144 x
+    assert_eq!(user_x.amount, 10000000 - 500000 + 149793);
+    assert_eq!(user_y.amount, 10000000 - 750000);
+    assert_eq!(commision_y.amount, 367);
+}
+
+// test of flash loan repaid with and without the commision
+#[tokio::test]
+async fn flash_loan() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let callback_accounts = vec![
+        AccountMeta::new(env.user_02_x_token_account.pubkey(), false),
+        AccountMeta::new(env.pool_x_token_account.pubkey(), false),
+        AccountMeta::new_readonly(env.user_02.pubkey(), true),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+
+    // commision of 100000 is 300, repaying 1 token less fails
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::flash_loan(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.borrower_program,
+            &env.mint_x_account.pubkey(),
+            100000,
+            100299u64.to_le_bytes().to_vec(),
+            callback_accounts.clone(),
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::FlashLoanNotRepaid as u32)
+        )
+    );
+
+    // the loan is observed at the reserves held until it
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp += 100;
+    env.ctx.set_sysvar(&clock);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::flash_loan(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.borrower_program,
+            &env.mint_x_account.pubkey(),
+            100000,
+            100300u64.to_le_bytes().to_vec(),
+            callback_accounts,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_02_x_token_account = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_x_token_account =
+        Account::unpack_from_slice(user_02_x_token_account.data.as_slice()).unwrap();
+    assert_eq!(user_02_x_token_account.amount, 10000000 - 300);
+
+    let pool_x_token_account = env
+        .ctx
+        .banks_client
+        .get_account(env.pool_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let pool_x_token_account =
+        Account::unpack_from_slice(pool_x_token_account.data.as_slice()).unwrap();
+    assert_eq!(pool_x_token_account.amount, 500000);
+
+    let commision_x_token_account = env
+        .ctx
+        .banks_client
+        .get_account(env.commision_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let commision_x_token_account =
+        Account::unpack_from_slice(commision_x_token_account.data.as_slice()).unwrap();
+    assert_eq!(commision_x_token_account.amount, 300);
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Observations::get_observations_pubkey(&env.pool))
+        .await
+        .unwrap()
+        .unwrap();
+    let observations = Observations::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(observations.latest().timestamp, clock.unix_timestamp);
+
+    // the commision is earned by the liquidity provider
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_fee(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_01_x_token_account = env
+        .ctx
+        .banks_client
+        .get_account(env.user_01_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_01_x_token_account =
+        Account::unpack_from_slice(user_01_x_token_account.data.as_slice()).unwrap();
+    assert_eq!(user_01_x_token_account.amount, 10000000 - 500000 + 299);
+}
+
+// test of price accumulators updated by provide, swap and withdraw
+#[tokio::test]
+async fn price_accumulators() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pool = Pool::try_from_slice(acc.data.as_slice()).unwrap();
+    let start = pool.observation();
+
+    // nothing accumulates before the first deposit
+    assert_eq!(pool.price_x_cumulative, 0);
+    assert_eq!(pool.price_y_cumulative, 0);
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = start.timestamp + 100;
+    env.ctx.set_sysvar(&clock);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250750,
+            0,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pool = Pool::try_from_slice(acc.data.as_slice()).unwrap();
+
+    // the price before the swap was held for 100 seconds
+    let price_x_before = math::price_q64(500000, 750000);
+    let price_y_before = math::price_q64(750000, 500000);
+    assert_eq!(pool.last_update_timestamp, start.timestamp + 100);
+    assert_eq!(pool.price_x_cumulative, price_x_before * 100);
+    assert_eq!(pool.price_y_cumulative, price_y_before * 100);
+
+    let pool_x_token_account = env
+        .ctx
+        .banks_client
+        .get_account(env.pool_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let reserve_x = Account::unpack_from_slice(pool_x_token_account.data.as_slice())
+        .unwrap()
+        .amount;
+    let pool_y_token_account = env
+        .ctx
+        .banks_client
+        .get_account(env.pool_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let reserve_y = Account::unpack_from_slice(pool_y_token_account.data.as_slice())
+        .unwrap()
+        .amount;
+
+    clock.unix_timestamp = start.timestamp + 300;
+    env.ctx.set_sysvar(&clock);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            1000,
+            0,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pool = Pool::try_from_slice(acc.data.as_slice()).unwrap();
+
+    // the price after the swap was held for 200 seconds
+    let price_x_after = math::price_q64(reserve_x, reserve_y);
+    let price_y_after = math::price_q64(reserve_y, reserve_x);
+    assert_eq!(
+        pool.price_x_cumulative,
+        price_x_before * 100 + price_x_after * 200
+    );
+    assert_eq!(
+        pool.price_y_cumulative,
+        price_y_before * 100 + price_y_after * 200
+    );
+
+    let (twap_x, twap_y) = start.twap(&pool.observation()).unwrap();
+    assert_eq!(twap_x, (price_x_before * 100 + price_x_after * 200) / 300);
+    assert_eq!(twap_y, (price_y_before * 100 + price_y_after * 200) / 300);
+    assert!(start.twap(&start).is_none());
+}
+
+async fn simulate_observe_twap(
+    env: &mut Env,
+    seconds_ago: u32,
+) -> Result<ObservedTwap, TransactionError> {
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::observe_twap(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            seconds_ago,
+        )],
+        Some(&env.ctx.payer.pubkey()),
+        &[&env.ctx.payer],
+        env.ctx.last_blockhash,
+    );
+
+    let simulation = env.ctx.banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap()?;
+
+    // the runtime trims trailing zeros of the return data
+    let mut data = simulation
+        .simulation_details
+        .unwrap()
+        .return_data
+        .unwrap()
+        .data;
+    data.resize(48, 0);
+    Ok(ObservedTwap::try_from_slice(&data).unwrap())
+}
+
+async fn get_reserves(env: &mut Env) -> (u64, u64, u64) {
+    let mut amounts = vec![];
+    for token_account in [
+        env.pool_x_token_account.pubkey(),
+        env.pool_y_token_account.pubkey(),
+    ] {
+        let acc = env
+            .ctx
+            .banks_client
+            .get_account(token_account)
+            .await
+            .unwrap()
+            .unwrap();
+        amounts.push(
+            Account::unpack_from_slice(acc.data.as_slice())
+                .unwrap()
+                .amount,
+        );
+    }
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.mint_lp_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let supply = Mint::unpack_from_slice(acc.data.as_slice()).unwrap().supply;
+    (amounts[0], amounts[1], supply)
+}
+
+// test of twap over windows ending now, read from the observations
+#[tokio::test]
+async fn observe_twap() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            PoolInstruction::provide_liquidity(
+                &env.user_01.pubkey(),
+                &env.pool,
+                &env.user_01_x_token_account.pubkey(),
+                &env.user_01_y_token_account.pubkey(),
+                &env.user_01_lp_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.commision_y_token_account.pubkey(),
+                500000,
+                750000,
+                0,
+                None,
+            ),
+            PoolInstruction::grow_observations(&env.user_01.pubkey(), &env.pool, 4),
+        ],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool)
+        .await
+        .unwrap()
+        .unwrap();
+    let t0 = Pool::try_from_slice(acc.data.as_slice())
+        .unwrap()
+        .last_update_timestamp;
+    let (x0, y0, lp0) = get_reserves(&mut env).await;
+
+    let mut clock: Clock = env.ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = t0 + 100;
+    env.ctx.set_sysvar(&clock);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            250750,
+            0,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let (x1, y1, lp1) = get_reserves(&mut env).await;
+
+    clock.unix_timestamp = t0 + 300;
+    env.ctx.set_sysvar(&clock);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            100000,
+            0,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let (x2, y2, lp2) = get_reserves(&mut env).await;
+
+    clock.unix_timestamp = t0 + 400;
+    env.ctx.set_sysvar(&clock);
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Observations::get_observations_pubkey(&env.pool))
+        .await
+        .unwrap()
+        .unwrap();
+    let observations = Observations::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(observations.observations.len(), 4);
+    assert_eq!(observations.cardinality, 3);
+    assert_eq!(observations.latest().timestamp, t0 + 300);
+
+    // whole history: three prices held for 100, 200 and 100 seconds
+    let twap = simulate_observe_twap(&mut env, 400).await.unwrap();
+    assert_eq!(
+        twap.price_x,
+        (math::price_q64(x0, y0) * 100
+            + math::price_q64(x1, y1) * 200
+            + math::price_q64(x2, y2) * 100)
+            / 400
+    );
+    assert_eq!(
+        twap.price_y,
+        (math::price_q64(y0, x0) * 100
+            + math::price_q64(y1, x1) * 200
+            + math::price_q64(y2, x2) * 100)
+            / 400
+    );
+    assert_eq!(
+        twap.liquidity,
+        (lp0 as u128 * 100 + lp1 as u128 * 200 + lp2 as u128 * 100) / 400
+    );
+
+    // window starting between the swap and the withdraw observations
+    let twap = simulate_observe_twap(&mut env, 200).await.unwrap();
+    assert_eq!(
+        twap.price_x,
+        (math::price_q64(x1, y1) * 100 + math::price_q64(x2, y2) * 100) / 200
+    );
+    assert_eq!(
+        twap.liquidity,
+        (lp1 as u128 * 100 + lp2 as u128 * 100) / 200
+    );
+
+    // window older than the history or empty
+    for seconds_ago in [401, 0] {
+        let err = simulate_observe_twap(&mut env, seconds_ago)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(PoolError::InvalidObservationWindow as u32)
+            )
+        );
+    }
+}
+
+// growing the observations checks the system program and is limited to 10KB
+// per instruction
+#[tokio::test]
+async fn grow_observations_limits() {
+    let mut env = Env::new().await;
+
+    let mut wrong_system_program =
+        PoolInstruction::grow_observations(&env.user_01.pubkey(), &env.pool, 4);
+    wrong_system_program.accounts[3].pubkey = spl_token::id();
+    let cases = [
+        (wrong_system_program, PoolError::InvalidSystemProgram),
+        (
+            PoolInstruction::grow_observations(&env.user_01.pubkey(), &env.pool, u16::MAX),
+            PoolError::ObservationsGrowthTooLarge,
+        ),
+    ];
+    for (instruction, error) in cases {
+        let tx = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&env.user_01.pubkey()),
+            &[&env.user_01],
+            env.ctx.last_blockhash,
+        );
+        let err = env
+            .ctx
+            .banks_client
+            .process_transaction(tx)
+            .await
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+        );
+    }
+
+    let observations = Observations::get_observations_pubkey(&env.pool);
+    let before = env
+        .ctx
+        .banks_client
+        .get_account(observations)
+        .await
+        .unwrap()
+        .unwrap()
+        .data
+        .len();
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::grow_observations(
+            &env.user_01.pubkey(),
+            &env.pool,
+            100,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(observations)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(acc.data.len() > before);
+    let observations = Observations::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(observations.observations.len(), 100);
+}
+
+// test of swaps on the stable swap curve of a balanced pool
+#[tokio::test]
+async fn swap_stable_curve() {
+    let mut env = Env::with_config(PoolConfig {
+        curve: CurveType::StableSwap { amp: 100 },
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            500000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the invariant of a balanced pool is the sum of its reserves
+    let user_01_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
+    assert_eq!(user_01_lp, 1000000 - 1000);
+
+    // 10000 with commision buys 9969, constant product would give 9775
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            10000,
+            9969,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_y_token_account = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(user_02_y_token_account.amount, 10000000 + 9969);
+
+    // buying the x back from the x heavy pool costs 10000 and 30 commision
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            10000,
+            10030,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_x_token_account = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(user_02_x_token_account.amount, 10000000);
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_y_token_account = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(user_02_y_token_account.amount, 10000000 + 9969 - 10030);
+}
+
+// test of swaps priced by the weights of an 80/20 pool
+#[tokio::test]
+async fn swap_weighted_curve() {
+    let mut env = Env::with_config(PoolConfig {
+        curve: CurveType::Weighted {
+            weight_x: 80,
+            weight_y: 20,
+        },
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            800000,
+            50000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // 800000^0.8 * 50000^0.2 = 459479.34
+    let user_01_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
+    assert_eq!(user_01_lp, 459479 - 1000);
+
+    // x is worth 4 * 50000 / 800000 = 0.25 y, constant product would give 615
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            10000,
+            2416,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_y_token_account = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(user_02_y_token_account.amount, 10000000 + 2416);
+
+    // 1000 x costs 236 y and 1 commision
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            1000,
+            237,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_x_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_x_token_account = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(user_02_x_token_account.amount, 10000000 - 10000 + 1000);
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.user_02_y_token_account.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let user_02_y_token_account = Account::unpack_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(user_02_y_token_account.amount, 10000000 + 2416 - 237);
+}
+
+async fn get_token_balance(ctx: &mut ProgramTestContext, token_account: Pubkey) -> u64 {
+    get_token_account(ctx, token_account).await.amount
+}
+
+async fn get_token_account(ctx: &mut ProgramTestContext, token_account: Pubkey) -> Account {
+    let acc = ctx
+        .banks_client
+        .get_account(token_account)
+        .await
+        .unwrap()
+        .unwrap();
+    Account::unpack_from_slice(acc.data.as_slice()).unwrap()
+}
+
+async fn get_mint(ctx: &mut ProgramTestContext, mint: Pubkey) -> Mint {
+    let acc = ctx.banks_client.get_account(mint).await.unwrap().unwrap();
+    Mint::unpack_from_slice(acc.data.as_slice()).unwrap()
+}
+
+// lp tokens staked by the fee position of `lp_token_account`
+async fn get_staked_lp(
+    ctx: &mut ProgramTestContext,
+    pool: &Pubkey,
+    lp_token_account: &Pubkey,
+) -> u64 {
+    let acc = ctx
+        .banks_client
+        .get_account(WithdrawedFee::get_withdraw_pubkey(pool, lp_token_account))
+        .await
+        .unwrap()
+        .unwrap();
+    WithdrawedFee::try_from_slice(acc.data.as_slice())
+        .unwrap()
+        .lp_staked
+}
+
+async fn get_pool(env: &mut Env) -> Pool {
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(env.pool)
+        .await
+        .unwrap()
+        .unwrap();
+    Pool::try_from_slice(acc.data.as_slice()).unwrap()
+}
+
+#[tokio::test]
+async fn concentrated_liquidity() {
+    let mut env = Env::with_config(PoolConfig {
+        curve: CurveType::Concentrated {
+            tick_spacing: 10,
+            initial_sqrt_price: math::Q64,
+        },
+        ..PoolConfig::default()
+    })
+    .await;
+
+    // full range liquidity is not available on a concentrated pool
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            100000,
+            100000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::WrongCurve as u32)
+        )
+    );
+
+    // ticks must be multiples of the tick spacing
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_concentrated(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            -1005,
+            1000,
+            100000,
+            100000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidTickRange as u32)
+        )
+    );
+
+    // lamports sent to the ticks and position addresses block nothing
+    let rent = env.ctx.banks_client.get_rent().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(
+                &env.user_01.pubkey(),
+                &get_ticks_pubkey(&env.pool),
+                rent.minimum_balance(0),
+            ),
+            system_instruction::transfer(
+                &env.user_01.pubkey(),
+                &Position::get_position_pubkey(&env.pool, &env.user_01.pubkey(), -1000, 1000),
+                rent.minimum_balance(0),
+            ),
+        ],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // price 1 in the middle of [-1000, 1000) takes both tokens, the range
+    // [-2000, -1000) below the price only y
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            PoolInstruction::provide_concentrated(
+                &env.user_01.pubkey(),
+                &env.pool,
+                &env.user_01_x_token_account.pubkey(),
+                &env.user_01_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.commision_y_token_account.pubkey(),
+                -1000,
+                1000,
+                100000,
+                100000,
+                0,
+                None,
+            ),
+            PoolInstruction::provide_concentrated(
+                &env.user_01.pubkey(),
+                &env.pool,
+                &env.user_01_x_token_account.pubkey(),
+                &env.user_01_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.commision_y_token_account.pubkey(),
+                -2000,
+                -1000,
+                0,
+                100000,
+                0,
+                None,
+            ),
+        ],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let position_pubkey =
+        Position::get_position_pubkey(&env.pool, &env.user_01.pubkey(), -1000, 1000);
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(position_pubkey)
+        .await
+        .unwrap()
+        .unwrap();
+    let position = Position::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(position.owner, env.user_01.pubkey());
+    assert_eq!(position.liquidity, 2050516);
+
+    let lower_position_pubkey =
+        Position::get_position_pubkey(&env.pool, &env.user_01.pubkey(), -2000, -1000);
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(lower_position_pubkey)
+        .await
+        .unwrap()
+        .unwrap();
+    let lower_position = Position::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(lower_position.liquidity, 2155643);
+
+    // only the range holding the price is active
+    let pool = get_pool(&mut env).await;
+    assert_eq!(pool.sqrt_price, math::Q64);
+    assert_eq!(pool.tick_current, 0);
+    assert_eq!(pool.liquidity, position.liquidity);
+    let pool_x = get_token_balance(&mut env.ctx, env.pool_x_token_account.pubkey()).await;
+    assert_eq!(pool_x, 100000);
+    let pool_y = get_token_balance(&mut env.ctx, env.pool_y_token_account.pubkey()).await;
+    assert_eq!(pool_y, 200000);
+
+    // the quote walks the same ticks without moving the pool
+    let quote: SwapQuote = simulate_quote(
+        &mut env.ctx,
+        PoolInstruction::quote_swap(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            150000,
+            true,
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(quote.amount_in, 150000);
+    assert_eq!(quote.amount_out, 139421);
+    assert_eq!(quote.commision_amount, 450);
+
+    let err = simulate_quote::<DepositQuote>(
+        &mut env.ctx,
+        PoolInstruction::quote_deposit(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            100000,
+            100000,
+        ),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::WrongCurve as u32)
+        )
+    );
+
+    // 105131 x and 316 commision buy all 100000 y of [-1000, 1000), the rest
+    // crosses tick -1000 and buys 39421 y of [-2000, -1000) for 134 commision
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_exact_in(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_x_token_account.pubkey(),
+            &env.user_02_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            150000,
+            0,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let pool = get_pool(&mut env).await;
+    assert_eq!(pool.tick_current, -1389);
+    assert_eq!(pool.liquidity, lower_position.liquidity);
+    let user_02_y = get_token_balance(&mut env.ctx, env.user_02_y_token_account.pubkey()).await;
+    assert_eq!(user_02_y, 10000000 + 139421);
+    let commision_x = get_token_balance(&mut env.ctx, env.commision_x_token_account.pubkey()).await;
+    assert_eq!(commision_x, 450);
+
+    // buying x back crosses up into [-1000, 1000) again, 119 commision is
+    // paid inside [-2000, -1000) and 155 inside [-1000, 1000)
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            100000,
+            u64::MAX,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let pool = get_pool(&mut env).await;
+    assert_eq!(pool.tick_current, -478);
+    assert_eq!(pool.liquidity, position.liquidity);
+    let user_02_x = get_token_balance(&mut env.ctx, env.user_02_x_token_account.pubkey()).await;
+    assert_eq!(user_02_x, 10000000 - 150000 + 100000);
+    let user_02_y = get_token_balance(&mut env.ctx, env.user_02_y_token_account.pubkey()).await;
+    assert_eq!(user_02_y, 10000000 + 139421 - 91318);
+    let commision_y = get_token_balance(&mut env.ctx, env.commision_y_token_account.pubkey()).await;
+    assert_eq!(commision_y, 274);
+
+    // withdrawing all of [-1000, 1000) pays out 149548 x and 51620 y with
+    // the commision earned inside the range, rounded down
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_concentrated(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            -1000,
+            1000,
+            position.liquidity,
+            0,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_01_x = get_token_balance(&mut env.ctx, env.user_01_x_token_account.pubkey()).await;
+    assert_eq!(user_01_x, 10000000 - 100000 + 149548 + 315);
+    let user_01_y = get_token_balance(&mut env.ctx, env.user_01_y_token_account.pubkey()).await;
+    assert_eq!(user_01_y, 10000000 - 200000 + 51620 + 154);
+    let commision_x = get_token_balance(&mut env.ctx, env.commision_x_token_account.pubkey()).await;
+    assert_eq!(commision_x, 450 - 315);
+    let commision_y = get_token_balance(&mut env.ctx, env.commision_y_token_account.pubkey()).await;
+    assert_eq!(commision_y, 274 - 154);
+    let pool = get_pool(&mut env).await;
+    assert_eq!(pool.liquidity, 0);
+
+    // tick 1000 bounds no other position and is dropped
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(get_ticks_pubkey(&env.pool))
+        .await
+        .unwrap()
+        .unwrap();
+    let ticks = Ticks::try_from_slice(acc.data.as_slice()).unwrap();
+    let indexes: Vec<i32> = ticks.ticks.iter().map(|tick| tick.index).collect();
+    assert_eq!(indexes, vec![-2000, -1000]);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_concentrated(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            -1000,
+            1000,
+            1,
+            0,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::OverWithdraw as u32)
+        )
+    );
+}
+
+// positions hold at least the minimum liquidity and the pool at most
+// `MAX_TICKS` initialized ticks
+#[tokio::test]
+async fn concentrated_ticks_limits() {
+    let mut env = Env::with_config(PoolConfig {
+        curve: CurveType::Concentrated {
+            tick_spacing: 10,
+            initial_sqrt_price: math::Q64,
+        },
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let provide = |env: &Env, tick_lower: i32, max_y: u64| {
+        PoolInstruction::provide_concentrated(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            tick_lower,
+            tick_lower + 10,
+            0,
+            max_y,
+            0,
+            None,
+        )
+    };
+    let expect_error = |err: TransactionError, error: PoolError| {
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+        );
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[provide(&env, -20, 100)],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    expect_error(err, PoolError::PositionTooSmall);
+
+    // ranges of one tick spacing below the price, every one with new ticks
+    let tick_lowers: Vec<i32> = (0..concentrated::MAX_TICKS as i32 / 2)
+        .map(|i| -20 * (i + 1))
+        .collect();
+    for chunk in tick_lowers.chunks(4) {
+        let instructions: Vec<Instruction> = chunk
+            .iter()
+            .map(|&tick_lower| provide(&env, tick_lower, 1000))
+            .collect();
+        let tx = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&env.user_01.pubkey()),
+            &[&env.user_01],
+            env.ctx.last_blockhash,
+        );
+        env.ctx.banks_client.process_transaction(tx).await.unwrap();
+    }
+
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(get_ticks_pubkey(&env.pool))
+        .await
+        .unwrap()
+        .unwrap();
+    let ticks = Ticks::try_from_slice(acc.data.as_slice()).unwrap();
+    assert_eq!(ticks.ticks.len(), concentrated::MAX_TICKS);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[provide(
+            &env,
+            -20 * (concentrated::MAX_TICKS as i32 / 2 + 1),
+            1000,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    expect_error(err, PoolError::TooManyTicks);
+
+    // existing ticks take more liquidity
+    let tx = Transaction::new_signed_with_payer(
+        &[provide(&env, -20, 1001)],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // a position is withdrawn whole or keeps the minimum
+    let acc = env
+        .ctx
+        .banks_client
+        .get_account(Position::get_position_pubkey(
+            &env.pool,
+            &env.user_01.pubkey(),
+            -20,
+            -10,
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let position = Position::try_from_slice(acc.data.as_slice()).unwrap();
+    let withdraw = |liquidity: u128| {
+        PoolInstruction::withdraw_concentrated(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            -20,
+            -10,
+            liquidity,
+            0,
+            0,
+            None,
+        )
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[withdraw(position.liquidity - 1)],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    expect_error(err, PoolError::PositionTooSmall);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[withdraw(position.liquidity)],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+/// Pool of the y token and a new z token, for routes through two pools.
+struct RoutePool {
+    pool: Pubkey,
+    mint_lp_account: Keypair,
+    pool_y_token_account: Keypair,
+    pool_z_token_account: Keypair,
+    commision_y_token_account: Keypair,
+    commision_z_token_account: Keypair,
+    user_02_z_token_account: Keypair,
+}
+
+async fn create_mint(ctx: &mut ProgramTestContext, mint: &Keypair, authority: &Pubkey) {
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &ctx.payer.pubkey(),
+                &mint.pubkey(),
+                rent.minimum_balance(Mint::LEN),
+                Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                &mint.pubkey(),
+                authority,
+                None,
+                9,
+            )
+            .unwrap(),
+        ],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, mint],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+async fn create_token_account(
+    ctx: &mut ProgramTestContext,
+    account: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) {
+    let rent = ctx.banks_client.get_rent().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &ctx.payer.pubkey(),
+                &account.pubkey(),
+                rent.minimum_balance(Account::LEN),
+                Account::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                &account.pubkey(),
+                mint,
+                owner,
+            )
+            .unwrap(),
+        ],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, account],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await.unwrap();
+}
+
+/// Creates a constant product y/z pool with 1000000 y and 2000000 z
+/// provided by the admin.
+async fn create_route_pool(env: &mut Env) -> RoutePool {
+    let mint_z_account = Keypair::new();
+    let mint_lp_account = Keypair::new();
+    let pool = Pool::get_pool_pubkey(&env.mint_y_account.pubkey(), &mint_z_account.pubkey());
+    let pool_authority = Pool::get_authority_pubkey(&pool);
+    let admin = env.admin.pubkey();
+    create_mint(&mut env.ctx, &mint_z_account, &admin).await;
+    create_mint(&mut env.ctx, &mint_lp_account, &pool_authority).await;
+
+    let route_pool = RoutePool {
+        pool,
+        mint_lp_account,
+        pool_y_token_account: Keypair::new(),
+        pool_z_token_account: Keypair::new(),
+        commision_y_token_account: Keypair::new(),
+        commision_z_token_account: Keypair::new(),
+        user_02_z_token_account: Keypair::new(),
+    };
+    let admin_y_token_account = Keypair::new();
+    let admin_z_token_account = Keypair::new();
+    let admin_lp_token_account = Keypair::new();
+    let mint_y = env.mint_y_account.pubkey();
+    let mint_z = mint_z_account.pubkey();
+    let mint_lp = route_pool.mint_lp_account.pubkey();
+    for (account, mint, owner) in [
+        (&route_pool.pool_y_token_account, &mint_y, &pool_authority),
+        (&route_pool.pool_z_token_account, &mint_z, &pool_authority),
+        (
+            &route_pool.commision_y_token_account,
+            &mint_y,
+            &pool_authority,
+        ),
+        (
+            &route_pool.commision_z_token_account,
+            &mint_z,
+            &pool_authority,
+        ),
+        (
+            &route_pool.user_02_z_token_account,
+            &mint_z,
+            &env.user_02.pubkey(),
+        ),
+        (&admin_y_token_account, &mint_y, &admin),
+        (&admin_z_token_account, &mint_z, &admin),
+        (&admin_lp_token_account, &mint_lp, &admin),
+    ] {
+        create_token_account(&mut env.ctx, account, mint, owner).await;
+    }
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &mint_y,
+                &admin_y_token_account.pubkey(),
+                &admin,
+                &[&admin],
+                1000000,
+            )
+            .unwrap(),
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &mint_z,
+                &admin_z_token_account.pubkey(),
+                &admin,
+                &[&admin],
+                2000000,
+            )
+            .unwrap(),
+            PoolInstruction::initialize_pool(
+                &admin,
+                &mint_y,
+                &mint_z,
+                &route_pool.pool_y_token_account.pubkey(),
+                &route_pool.pool_z_token_account.pubkey(),
+                &mint_lp,
+                &route_pool.commision_y_token_account.pubkey(),
+                &route_pool.commision_z_token_account.pubkey(),
+                &admin_y_token_account.pubkey(),
+                &admin_z_token_account.pubkey(),
+                3,
+                1000,
+                0,
+                1,
+                false,
+                CurveType::ConstantProduct,
+            ),
+            PoolInstruction::provide_liquidity(
+                &admin,
+                &pool,
+                &admin_y_token_account.pubkey(),
+                &admin_z_token_account.pubkey(),
+                &admin_lp_token_account.pubkey(),
+                &route_pool.pool_y_token_account.pubkey(),
+                &route_pool.pool_z_token_account.pubkey(),
+                &mint_lp,
+                &route_pool.commision_y_token_account.pubkey(),
+                &route_pool.commision_z_token_account.pubkey(),
+                1000000,
+                2000000,
+                0,
+                None,
+            ),
+        ],
+        Some(&admin),
+        &[&env.admin],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    route_pool
+}
+
+// swap of x to z through the x/y and y/z pools
+#[tokio::test]
+async fn route_swap() {
+    let mut env = Env::new().await;
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            1000000,
+            1000000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let route_pool = create_route_pool(&mut env).await;
+    let hops = [
+        SwapHop {
+            pool: env.pool,
+            from_user_token: env.user_02_x_token_account.pubkey(),
+            to_user_token: env.user_02_y_token_account.pubkey(),
+            pool_from_token: env.pool_x_token_account.pubkey(),
+            pool_to_token: env.pool_y_token_account.pubkey(),
+            commision_from_token: env.commision_x_token_account.pubkey(),
+            mint_lp_token: env.mint_lp_account.pubkey(),
+        },
+        SwapHop {
+            pool: route_pool.pool,
+            from_user_token: env.user_02_y_token_account.pubkey(),
+            to_user_token: route_pool.user_02_z_token_account.pubkey(),
+            pool_from_token: route_pool.pool_y_token_account.pubkey(),
+            pool_to_token: route_pool.pool_z_token_account.pubkey(),
+            commision_from_token: route_pool.commision_y_token_account.pubkey(),
+            mint_lp_token: route_pool.mint_lp_account.pubkey(),
+        },
+    ];
+
+    // the second hop pays x instead of the y received by the first one
+    let unchained_hops = [hops[0].clone(), hops[0].clone()];
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::route_swap(
+            &env.user_02.pubkey(),
+            &unchained_hops,
+            100300,
+            0,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::InvalidRoute as u32)
+        )
+    );
+
+    // 100000 x and 300 commision buy 90909 y, 90637 y and 272 commision
+    // buy 166209 z, only the final output is checked
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::route_swap(
+            &env.user_02.pubkey(),
+            &hops,
+            100300,
+            166210,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    let err = env
+        .ctx
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(PoolError::SlippageExceeded as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::route_swap(
+            &env.user_02.pubkey(),
+            &hops,
+            100300,
+            166209,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_02_x = get_token_balance(&mut env.ctx, env.user_02_x_token_account.pubkey()).await;
+    assert_eq!(user_02_x, 10000000 - 100300);
+    let user_02_y = get_token_balance(&mut env.ctx, env.user_02_y_token_account.pubkey()).await;
+    assert_eq!(user_02_y, 10000000);
+    let user_02_z =
+        get_token_balance(&mut env.ctx, route_pool.user_02_z_token_account.pubkey()).await;
+    assert_eq!(user_02_z, 166209);
+    let commision_x = get_token_balance(&mut env.ctx, env.commision_x_token_account.pubkey()).await;
+    assert_eq!(commision_x, 300);
+    let commision_y =
+        get_token_balance(&mut env.ctx, route_pool.commision_y_token_account.pubkey()).await;
+    assert_eq!(commision_y, 272);
+    let pool_y = get_token_balance(&mut env.ctx, route_pool.pool_y_token_account.pubkey()).await;
+    assert_eq!(pool_y, 1000000 + 90637);
+}
+
+// swaps and a fee withdraw on the x/y pool leave the y/z pool of the same
+// deployment untouched
+#[tokio::test]
+async fn pools_independent() {
+    let mut env = Env::new().await;
+    let other = create_route_pool(&mut env).await;
+
+    let pool_accounts = |pool: &Pubkey| {
+        [
+            *pool,
+            Pool::get_authority_pubkey(pool),
+            Pool::get_locked_lp_pubkey(pool),
+            Pool::get_staked_lp_pubkey(pool),
+            Observations::get_observations_pubkey(pool),
+        ]
+    };
+    let xy_accounts = pool_accounts(&env.pool);
+    let yz_accounts = pool_accounts(&other.pool);
+    for account in xy_accounts {
+        assert!(!yz_accounts.contains(&account));
+    }
+
+    let mut watched = yz_accounts.to_vec();
+    watched.extend([
+        other.mint_lp_account.pubkey(),
+        other.pool_y_token_account.pubkey(),
+        other.pool_z_token_account.pubkey(),
+        other.commision_y_token_account.pubkey(),
+        other.commision_z_token_account.pubkey(),
+    ]);
+    let mut before = Vec::new();
+    for account in &watched {
+        before.push(env.ctx.banks_client.get_account(*account).await.unwrap());
+    }
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            PoolInstruction::swap_tokens(
+                &env.user_02.pubkey(),
+                &env.pool,
+                &env.user_02_x_token_account.pubkey(),
+                &env.user_02_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                50000,
+                50000,
+                None,
+            ),
+            PoolInstruction::swap_tokens(
+                &env.user_02.pubkey(),
+                &env.pool,
+                &env.user_02_y_token_account.pubkey(),
+                &env.user_02_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.commision_y_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                20000,
+                50000,
+                None,
+            ),
+        ],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let x_before = get_token_balance(&mut env.ctx, env.user_01_x_token_account.pubkey()).await;
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_fee(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    // the x/y pool did earn and pay out commision
+    let pool = get_pool(&mut env).await;
+    assert!(pool.fee_growth_x > 0 && pool.fee_growth_y > 0);
+    assert!(get_token_balance(&mut env.ctx, env.user_01_x_token_account.pubkey()).await > x_before);
+
+    for (account, before) in watched.iter().zip(before) {
+        let after = env.ctx.banks_client.get_account(*account).await.unwrap();
+        assert_eq!(after, before);
+    }
+}
+
+async fn simulate_quote<T: BorshDeserialize>(
+    ctx: &mut ProgramTestContext,
+    instruction: Instruction,
+) -> Result<T, TransactionError> {
+    let tx = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        ctx.last_blockhash,
+    );
+
+    let simulation = ctx.banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap()?;
+
+    // the runtime trims trailing zeros of the return data
+    let mut data = simulation
+        .simulation_details
+        .unwrap()
+        .return_data
+        .unwrap()
+        .data;
+    data.resize(32, 0);
+    Ok(T::deserialize(&mut data.as_slice()).unwrap())
+}
+
+// quotes match the amounts of the instructions they preview
+#[tokio::test]
+async fn quote_instructions() {
+    let mut env = Env::new().await;
+
+    let quote: DepositQuote = simulate_quote(
+        &mut env.ctx,
+        PoolInstruction::quote_deposit(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            500000,
+            750000,
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(quote.x_amount, 500000);
+    assert_eq!(quote.y_amount, 750000);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_01_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
+    assert_eq!(user_01_lp, quote.lp_out);
+
+    // same amounts as the `swap_exact_in` test
+    let quote: SwapQuote = simulate_quote(
+        &mut env.ctx,
+        PoolInstruction::quote_swap(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            250750,
+            true,
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        quote,
+        SwapQuote {
+            amount_in: 250750,
+            amount_out: 250000,
+            swap_price: 250000,
+            commision_amount: 750,
+        }
+    );
+
+    // buying x back costs exactly the quoted amount in
+    let quote: SwapQuote = simulate_quote(
+        &mut env.ctx,
+        PoolInstruction::quote_swap(
+            &env.pool,
+            &env.pool_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            100000,
+            false,
+        ),
+    )
+    .await
+    .unwrap();
+    assert_eq!(quote.amount_out, 100000);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::swap_tokens(
+            &env.user_02.pubkey(),
+            &env.pool,
+            &env.user_02_y_token_account.pubkey(),
+            &env.user_02_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            100000,
+            quote.amount_in,
+            None,
+        )],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_02_y = get_token_balance(&mut env.ctx, env.user_02_y_token_account.pubkey()).await;
+    assert_eq!(user_02_y, 10000000 - quote.amount_in);
+    let commision_y = get_token_balance(&mut env.ctx, env.commision_y_token_account.pubkey()).await;
+    assert_eq!(commision_y, quote.commision_amount);
+
+    // buying the whole reserve is rejected as by the swap
+    let err = simulate_quote::<SwapQuote>(
+        &mut env.ctx,
+        PoolInstruction::quote_swap(
+            &env.pool,
+            &env.pool_y_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            600000,
+            false,
+        ),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(0, InstructionError::Custom(PoolError::OverBuy as u32))
+    );
+
+    let quote: WithdrawQuote = simulate_quote(
+        &mut env.ctx,
+        PoolInstruction::quote_withdraw(
+            &env.pool,
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            user_01_lp,
+        ),
+    )
+    .await
+    .unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            user_01_lp,
+            quote.x_amount,
+            quote.y_amount,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_01_x = get_token_balance(&mut env.ctx, env.user_01_x_token_account.pubkey()).await;
+    assert_eq!(user_01_x, 10000000 - 500000 + quote.x_amount);
+    let user_01_y = get_token_balance(&mut env.ctx, env.user_01_y_token_account.pubkey()).await;
+    // the withdraw also pays out the commision share, the locked lp keeps 1
+    assert_eq!(
+        user_01_y,
+        10000000 - 750000 + quote.y_amount + commision_y - 1
+    );
+}
+
+async fn get_snapshot(env: &mut Env) -> PoolSnapshot {
+    let pool = get_pool(env).await;
+    let (reserve_x, reserve_y, total_lp) = get_reserves(env).await;
+    pool.snapshot(reserve_x, reserve_y, total_lp)
+}
+
+// an off-chain snapshot prices and moves exactly as the pool does
+#[tokio::test]
+async fn pool_snapshot() {
+    let mut env = Env::with_config(PoolConfig {
+        protocol_fee_numerator: 1,
+        protocol_fee_denominator: 5,
+        ..PoolConfig::default()
+    })
+    .await;
+
+    let mut snapshot = get_snapshot(&mut env).await;
+    let [x_amount, y_amount, new_lp] = snapshot.deposit(500000, 750000).unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::provide_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            500000,
+            750000,
+            0,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert_eq!([x_amount, y_amount], [500000, 750000]);
+    let user_01_lp = get_staked_lp(
+        &mut env.ctx,
+        &env.pool,
+        &env.user_01_lp_token_account.pubkey(),
+    )
+    .await;
+    assert_eq!(user_01_lp, new_lp);
+
+    let amount_out = snapshot.swap_exact_in(100000, true).unwrap().amount_out;
+    let amount_in = snapshot
+        .swap_exact_out(50000, true)
+        .unwrap()
+        .amount_in()
+        .unwrap();
+
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            PoolInstruction::swap_exact_in(
+                &env.user_02.pubkey(),
+                &env.pool,
+                &env.user_02_x_token_account.pubkey(),
+                &env.user_02_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                100000,
+                amount_out,
+                None,
+            ),
+            PoolInstruction::swap_tokens(
+                &env.user_02.pubkey(),
+                &env.pool,
+                &env.user_02_x_token_account.pubkey(),
+                &env.user_02_y_token_account.pubkey(),
+                &env.pool_x_token_account.pubkey(),
+                &env.pool_y_token_account.pubkey(),
+                &env.commision_x_token_account.pubkey(),
+                &env.mint_lp_account.pubkey(),
+                50000,
+                amount_in,
+                None,
+            ),
+        ],
+        Some(&env.user_02.pubkey()),
+        &[&env.user_02],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    let user_02_y = get_token_balance(&mut env.ctx, env.user_02_y_token_account.pubkey()).await;
+    assert_eq!(user_02_y, 10000000 + amount_out + 50000);
+
+    // reserves, lp supply and fee growth follow the swaps
+    assert_eq!(get_snapshot(&mut env).await, snapshot);
+
+    let [x_amount, y_amount] = snapshot.withdraw(user_01_lp).unwrap();
+    assert_eq!(snapshot.total_lp, MINIMUM_LIQUIDITY);
+
+    let tx = Transaction::new_signed_with_payer(
+        &[PoolInstruction::withdraw_liquidity(
+            &env.user_01.pubkey(),
+            &env.pool,
+            &env.user_01_x_token_account.pubkey(),
+            &env.user_01_y_token_account.pubkey(),
+            &env.user_01_lp_token_account.pubkey(),
+            &env.pool_x_token_account.pubkey(),
+            &env.pool_y_token_account.pubkey(),
+            &env.mint_lp_account.pubkey(),
+            &env.commision_x_token_account.pubkey(),
+            &env.commision_y_token_account.pubkey(),
+            user_01_lp,
+            x_amount,
+            y_amount,
+            None,
+        )],
+        Some(&env.user_01.pubkey()),
+        &[&env.user_01],
+        env.ctx.last_blockhash,
+    );
+
+    env.ctx.banks_client.process_transaction(tx).await.unwrap();
+
+    assert_eq!(get_snapshot(&mut env).await, snapshot);
+}